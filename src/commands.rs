@@ -1,29 +1,52 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, time::SystemTime};
 
-use log::{debug, warn};
+use log::warn;
+use poise::serenity_prelude::{self as serenity, ChannelId, GuildId, Mentionable, UserId};
 use ron::ser::PrettyConfig;
 
-use crate::{Context, Error, DATA_PATH, EMBED_COLOR};
+use crate::{
+    data::{Config, Data, GuildMeta, Phrase, ResponseKind, VariantMode},
+    engine::{self, Incoming, Past, Skip},
+    listener::{self, Recent},
+    matcher::{self, model_name},
+    permissions::{manage_access, manage_channels, manage_config, manage_phrases, Area},
+    storage,
+    templates::{self, Trigger},
+    Context, Error, BOT_ID,
+};
+
+/// updates the matcher index after phrases changed
+///
+/// failures are only logged, the listener retries indexing before matching
+async fn index_phrases(data: &Data, guild_meta: &mut GuildMeta) {
+    let matcher = guild_meta.config.backend.matcher();
+    if let Err(err) = matcher.index(data, guild_meta).await {
+        warn!("error while indexing phrases: {}", err);
+    }
+}
 
 /// Adds a catchphrase
-#[poise::command(slash_command, owners_only)]
+#[poise::command(slash_command, check = "manage_phrases")]
 pub async fn add_catchphrase(
     ctx: Context<'_>,
     #[description = "Added catchphrase"] catchphrase: String,
     #[description = "Optional keywords to associate with that catchphrase"] keywords: Vec<String>,
 ) -> Result<(), Error> {
-    let data = ctx.data();
-
-    let guild_meta_lock = data
-        .get_guild(ctx.guild_id().unwrap())
-        .await
-        .expect("guild not found");
-    let mut guild_meta = guild_meta_lock.write().await;
+    let result = insert_phrase(
+        ctx.data(),
+        ctx.guild_id().unwrap(),
+        &catchphrase,
+        keywords.clone(),
+    )
+    .await;
 
     ctx.send(|r| {
         r.embed(|e| {
-            e.color(EMBED_COLOR);
+            e.color(ctx.data().settings.embed_color);
             e.title("Added catchphrase");
+            if let Err(err) = result {
+                return e.field("error: ", err, true);
+            }
             e.field("catchphrase: ", &catchphrase, true);
             if !keywords.is_empty() {
                 e.field(
@@ -40,10 +63,27 @@ pub async fn add_catchphrase(
     })
     .await?;
 
-    guild_meta.phrases.insert(
-        catchphrase.clone(),
-        keywords.into_iter().collect::<HashSet<_>>(),
-    );
+    Ok(())
+}
+
+/// adds a phrase, or replaces the keywords of a phrase that is added again
+/// and keeps its variants and settings
+async fn insert_phrase(
+    data: &Data,
+    guild_id: GuildId,
+    phrase: &str,
+    keywords: Vec<String>,
+) -> Result<(), String> {
+    // the phrase is sent as a response too
+    templates::validate(phrase).map_err(|err| err.to_string())?;
+
+    let guild_meta_lock = data.get_guild(guild_id).await.expect("guild not found");
+    let mut guild_meta = guild_meta_lock.write().await;
+
+    let entry = guild_meta.phrases.entry(phrase.to_owned()).or_default();
+    entry.keywords = keywords.into_iter().collect::<HashSet<_>>();
+    index_phrases(data, &mut guild_meta).await;
+    data.mark_dirty(guild_id);
 
     Ok(())
 }
@@ -62,24 +102,53 @@ pub async fn list_catchphrases(ctx: Context<'_>) -> Result<(), Error> {
     let phrases = &guild_meta.phrases;
 
     if !phrases.is_empty() {
-        let phrases = phrases.iter().map(|(phrase, keywords)| {
-            (
-                phrase,
-                format!(
-                    "keywords: {}",
-                    keywords
-                        .iter()
-                        .map(|keyword| &**keyword)
-                        .intersperse(", ")
-                        .collect::<String>()
-                ),
-                false,
-            )
+        let phrases = phrases.iter().map(|(phrase, entry)| {
+            let keywords = entry
+                .keywords
+                .iter()
+                .map(|keyword| &**keyword)
+                .intersperse(", ")
+                .collect::<String>();
+            let cooldown = match entry.cooldown {
+                Some(cooldown) => format!("{cooldown}s"),
+                None => "guild default".to_owned(),
+            };
+            let mut value = format!("keywords: {keywords}\ncooldown: {cooldown}");
+            match &entry.kind {
+                ResponseKind::Message => {}
+                ResponseKind::Reply => value += "\nsent as a reply",
+                ResponseKind::React(emoji) => {
+                    value += &format!("\nreacts with {}", emoji.join(" "))
+                }
+                ResponseKind::Sticker(sticker) => {
+                    value += &format!("\nsends sticker {}", sticker.0)
+                }
+            }
+            if let Some(weight) = entry.weight {
+                value += &format!("\nweight: {weight}");
+            }
+            if let Some(ping) = entry.ping {
+                value += &format!("\nreplies ping the author: {ping}");
+            }
+            if !entry.variants.is_empty() {
+                let mode = match entry.mode {
+                    VariantMode::Random => "random",
+                    VariantMode::Rotation => "rotation",
+                };
+                let variants = entry
+                    .variants
+                    .iter()
+                    .map(|variant| &**variant)
+                    .intersperse(" | ")
+                    .collect::<String>();
+                value += &format!("\nvariants ({mode}): {variants}");
+            }
+            (phrase, value, false)
         });
 
         ctx.send(|r| {
             r.embed(|e| {
-                e.color(EMBED_COLOR);
+                e.color(ctx.data().settings.embed_color);
                 e.title("Phrases");
                 e.fields(phrases)
             })
@@ -88,7 +157,7 @@ pub async fn list_catchphrases(ctx: Context<'_>) -> Result<(), Error> {
     } else {
         ctx.send(|r| {
             r.embed(|e| {
-                e.color(EMBED_COLOR);
+                e.color(ctx.data().settings.embed_color);
                 e.title("Removed phrase");
                 e.field("error: ", "this guild has no catchphrases", true)
             })
@@ -100,7 +169,7 @@ pub async fn list_catchphrases(ctx: Context<'_>) -> Result<(), Error> {
 }
 
 /// Removes a catchphrase
-#[poise::command(slash_command, owners_only)]
+#[poise::command(slash_command, check = "manage_phrases")]
 pub async fn remove_catchphrase(
     ctx: Context<'_>,
     #[description = "Removed catchphrase"] phrase: String,
@@ -114,11 +183,13 @@ pub async fn remove_catchphrase(
     let mut guild_meta = guild_meta_lock.write().await;
 
     let phrase_existed = guild_meta.phrases.remove(&phrase).is_some();
+    guild_meta.cooldown.remove(&phrase);
 
     if phrase_existed {
+        data.mark_dirty(ctx.guild_id().unwrap());
         ctx.send(|r| {
             r.embed(|e| {
-                e.color(EMBED_COLOR);
+                e.color(ctx.data().settings.embed_color);
                 e.title("Removed phrase");
                 e.field("phrase: ", &phrase, true)
             })
@@ -127,7 +198,7 @@ pub async fn remove_catchphrase(
     } else {
         ctx.send(|r| {
             r.embed(|e| {
-                e.color(EMBED_COLOR);
+                e.color(ctx.data().settings.embed_color);
                 e.title("Removed phrase");
                 e.field("error: ", "phrase not found", true)
             })
@@ -137,8 +208,586 @@ pub async fn remove_catchphrase(
 
     Ok(())
 }
-/// shows the bot config
-#[poise::command(slash_command, owners_only)]
+/// Adds a response variant to a catchphrase
+#[poise::command(slash_command, check = "manage_phrases")]
+pub async fn add_variant(
+    ctx: Context<'_>,
+    #[description = "the catchphrase"] phrase: String,
+    #[description = "text sent instead of the catchphrase"] variant: String,
+) -> Result<(), Error> {
+    edit_phrase(ctx, &phrase, |entry| {
+        if entry.variants.contains(&variant) {
+            return Err("variant already exists".to_owned());
+        }
+        templates::validate(&variant).map_err(|err| err.to_string())?;
+        entry.variants.push(variant.clone());
+        Ok(format!("added `{variant}`"))
+    })
+    .await
+}
+
+/// Removes a response variant from a catchphrase
+#[poise::command(slash_command, check = "manage_phrases")]
+pub async fn remove_variant(
+    ctx: Context<'_>,
+    #[description = "the catchphrase"] phrase: String,
+    #[description = "the variant to remove"] variant: String,
+) -> Result<(), Error> {
+    edit_phrase(ctx, &phrase, |entry| {
+        let len = entry.variants.len();
+        entry.variants.retain(|existing| *existing != variant);
+        if entry.variants.len() == len {
+            return Err("variant not found".to_owned());
+        }
+        Ok(format!("removed `{variant}`"))
+    })
+    .await
+}
+
+/// Sets how the response of a catchphrase is picked
+#[poise::command(slash_command, check = "manage_phrases")]
+pub async fn set_variant_mode(
+    ctx: Context<'_>,
+    #[description = "the catchphrase"] phrase: String,
+    #[description = "random responses, or each once before repeating"] mode: VariantMode,
+) -> Result<(), Error> {
+    edit_phrase(ctx, &phrase, |entry| {
+        entry.mode = mode;
+        Ok(format!("responses are picked by {mode:?}"))
+    })
+    .await
+}
+
+/// response kinds to pick from in commands
+#[derive(Clone, Copy, Debug, poise::SlashChoiceParameter)]
+pub enum Kind {
+    #[name = "message"]
+    Message,
+    #[name = "reply"]
+    Reply,
+    #[name = "react"]
+    React,
+    #[name = "sticker"]
+    Sticker,
+}
+
+/// Sets how a catchphrase is sent
+#[poise::command(slash_command, check = "manage_phrases")]
+pub async fn set_response_kind(
+    ctx: Context<'_>,
+    #[description = "the catchphrase"] phrase: String,
+    #[description = "message, reply, reaction or sticker"] kind: Kind,
+    #[description = "emoji to react with separated by spaces, or the sticker name or id"]
+    value: Option<String>,
+) -> Result<(), Error> {
+    let value = value.unwrap_or_default();
+
+    let kind = match kind {
+        Kind::Message => Ok(ResponseKind::Message),
+        Kind::Reply => Ok(ResponseKind::Reply),
+        Kind::React => {
+            let emoji = value
+                .split_whitespace()
+                .map(str::to_owned)
+                .collect::<Vec<_>>();
+            if emoji.is_empty() {
+                Err("no emoji given".to_owned())
+            } else if let Some(invalid) = emoji.iter().find(|emoji| !is_emoji(emoji)) {
+                Err(format!("`{invalid}` is not an emoji"))
+            } else {
+                check_reactions(ctx, &emoji)
+                    .await
+                    .map(|()| ResponseKind::React(emoji))
+            }
+        }
+        Kind::Sticker => {
+            // only stickers of this guild can be sent
+            let value = value.trim();
+            let stickers = ctx
+                .guild_id()
+                .unwrap()
+                .stickers(&ctx.discord().http)
+                .await?;
+            stickers
+                .iter()
+                .find(|sticker| sticker.name == value || sticker.id.to_string() == value)
+                .map(|sticker| ResponseKind::Sticker(sticker.id))
+                .ok_or_else(|| format!("no sticker `{value}` in this guild"))
+        }
+    };
+
+    edit_phrase(ctx, &phrase, |entry| {
+        let kind = kind?;
+        let change = format!("sent as {kind:?}");
+        entry.kind = kind;
+        Ok(change)
+    })
+    .await
+}
+
+/// Sets whether replies with a catchphrase ping the author
+#[poise::command(slash_command, check = "manage_phrases")]
+pub async fn set_reply_ping(
+    ctx: Context<'_>,
+    #[description = "the catchphrase"] phrase: String,
+    #[description = "ping the author, leave empty for the guild default"] ping: Option<bool>,
+) -> Result<(), Error> {
+    edit_phrase(ctx, &phrase, |entry| {
+        entry.ping = ping;
+        Ok(match ping {
+            Some(true) => "replies ping the author".to_owned(),
+            Some(false) => "replies don't ping the author".to_owned(),
+            None => "replies ping like the guild config says".to_owned(),
+        })
+    })
+    .await
+}
+
+/// Sets how much a catchphrase is favored when several phrases match
+#[poise::command(slash_command, check = "manage_phrases")]
+pub async fn set_phrase_weight(
+    ctx: Context<'_>,
+    #[description = "the catchphrase"] phrase: String,
+    #[description = "score multiplier up to 100, leave empty for 1"] weight: Option<f64>,
+) -> Result<(), Error> {
+    edit_phrase(ctx, &phrase, |entry| {
+        if weight.is_some_and(|weight| !(*weight > 0.0 && *weight <= 100.0)) {
+            return Err("the weight must be above 0 and at most 100".to_owned());
+        }
+        entry.weight = weight;
+        Ok(format!("score multiplied by {}", weight.unwrap_or(1.0)))
+    })
+    .await
+}
+
+/// checks that text is a guild emoji like `<:name:id>` or made of unicode emoji characters
+///
+/// serenity takes any text that isn't a guild emoji as unicode, so text like `:)` is rejected
+/// here, discord has the final say when the command reacts with it
+fn is_emoji(text: &str) -> bool {
+    if text.starts_with('<') {
+        return matches!(
+            text.parse::<serenity::ReactionType>(),
+            Ok(serenity::ReactionType::Custom { .. })
+        );
+    }
+
+    // digits, `#` and `*` are only emoji as keycaps like 1️⃣
+    if let Some(base) = text.strip_suffix('\u{20E3}') {
+        let base = base.strip_suffix('\u{FE0F}').unwrap_or(base);
+        return matches!(base.as_bytes(), [b'0'..=b'9' | b'#' | b'*']);
+    }
+
+    text.chars().all(is_emoji_char) && text.chars().any(is_pictograph)
+}
+
+/// whether a character is an emoji or joins or modifies emoji
+fn is_emoji_char(char: char) -> bool {
+    is_pictograph(char)
+        || matches!(
+            char as u32,
+            // zero width joiner, variation selectors and tags
+            0x200D | 0xFE0E | 0xFE0F | 0xE0020..=0xE007F
+        )
+}
+
+/// whether a character is shown as an emoji on its own
+fn is_pictograph(char: char) -> bool {
+    matches!(
+        char as u32,
+        0x00A9
+            | 0x00AE
+            | 0x203C
+            | 0x2049
+            | 0x2122
+            | 0x2139
+            | 0x2194..=0x21AA
+            | 0x231A..=0x23FF
+            | 0x24C2
+            | 0x25AA..=0x25FE
+            | 0x2600..=0x27BF
+            | 0x2934
+            | 0x2935
+            | 0x2B05..=0x2B55
+            | 0x3030
+            | 0x303D
+            | 0x3297
+            | 0x3299
+            | 0x1F000..=0x1FAFF
+    )
+}
+
+/// reacts to the response of the command with each emoji, so emoji discord rejects are caught
+/// before a phrase is saved with them instead of every time it is sent
+///
+/// the reactions are left as a preview of the response
+async fn check_reactions(ctx: Context<'_>, emoji: &[String]) -> Result<(), String> {
+    let response = ctx
+        .say("checking emoji")
+        .await
+        .map_err(|err| err.to_string())?;
+    let message = response.message().await.map_err(|err| err.to_string())?;
+
+    for emoji in emoji {
+        let reaction = emoji
+            .parse::<serenity::ReactionType>()
+            .map_err(|_| format!("`{emoji}` is not an emoji"))?;
+        if let Err(err) = message.react(ctx.discord(), reaction).await {
+            return Err(format!("discord can't react with `{emoji}`: {err}"));
+        }
+    }
+    Ok(())
+}
+
+/// applies an edit to a phrase and replies with the change
+async fn edit_phrase(
+    ctx: Context<'_>,
+    phrase: &str,
+    edit: impl FnOnce(&mut Phrase) -> Result<String, String>,
+) -> Result<(), Error> {
+    let result = update_phrase(ctx.data(), ctx.guild_id().unwrap(), phrase, edit).await;
+
+    ctx.send(|r| {
+        r.embed(|e| {
+            e.color(ctx.data().settings.embed_color);
+            e.title("Edit phrase");
+            e.field("phrase: ", phrase, true);
+            match result {
+                Ok(change) => e.field("change: ", change, true),
+                Err(err) => e.field("error: ", err, true),
+            }
+        })
+    })
+    .await?;
+    Ok(())
+}
+
+/// applies an edit to a phrase and restarts its rotation, in case its variants changed
+async fn update_phrase(
+    data: &Data,
+    guild_id: GuildId,
+    phrase: &str,
+    edit: impl FnOnce(&mut Phrase) -> Result<String, String>,
+) -> Result<String, String> {
+    let guild_meta_lock = data.get_guild(guild_id).await.expect("guild not found");
+    let mut guild_meta = guild_meta_lock.write().await;
+
+    let entry = guild_meta
+        .phrases
+        .get_mut(phrase)
+        .ok_or_else(|| "phrase not found".to_owned())?;
+    let change = edit(entry)?;
+    entry.rotation.clear();
+    data.mark_dirty(guild_id);
+
+    Ok(change)
+}
+
+/// Previews the responses of a catchphrase as if you triggered it here
+#[poise::command(slash_command, check = "manage_phrases")]
+pub async fn preview_phrase(
+    ctx: Context<'_>,
+    #[description = "the catchphrase"] phrase: String,
+) -> Result<(), Error> {
+    let data = ctx.data();
+
+    let guild_meta_lock = data
+        .get_guild(ctx.guild_id().unwrap())
+        .await
+        .expect("guild not found");
+    let guild_meta = guild_meta_lock.read().await;
+
+    let Some(entry) = guild_meta.phrases.get(&phrase) else {
+        ctx.send(|r| {
+            r.embed(|e| {
+                e.color(ctx.data().settings.embed_color);
+                e.title("Preview");
+                e.field("error: ", "phrase not found", true)
+            })
+        })
+        .await?;
+        return Ok(());
+    };
+
+    let rng = fastrand::Rng::new();
+    let trigger = Trigger {
+        author: ctx.author(),
+        channel: ctx.channel_id(),
+        guild: ctx.guild_id().unwrap(),
+        keyword: engine::matched_keyword(entry, ""),
+        count: entry.uses + 1,
+    };
+    let previews = std::iter::once(&phrase)
+        .chain(&entry.variants)
+        .map(|response| {
+            let preview = templates::render(response, &trigger, &ctx.discord().cache, &rng)
+                .unwrap_or_else(|err| format!("error: {err}"));
+            (response.clone(), preview, false)
+        })
+        .collect::<Vec<_>>();
+
+    ctx.send(|r| {
+        r.ephemeral(true);
+        r.embed(|e| {
+            e.color(ctx.data().settings.embed_color);
+            e.title("Preview");
+            e.fields(previews)
+        })
+    })
+    .await?;
+    Ok(())
+}
+
+/// Shows how the bot would score a message sent here, without responding
+#[poise::command(slash_command, check = "manage_config")]
+pub async fn test_match(
+    ctx: Context<'_>,
+    #[description = "the message to test"] text: String,
+) -> Result<(), Error> {
+    let incoming = Incoming {
+        author: ctx.author().id,
+        channel: ctx.channel_id(),
+    };
+    let history = [Past {
+        content: &text,
+        own: false,
+    }];
+    dry_run(ctx, incoming, &history).await
+}
+
+/// Shows how the bot scores a message and the messages before it, without responding
+#[poise::command(context_menu_command = "Test match", check = "manage_config")]
+pub async fn test_match_message(ctx: Context<'_>, message: serenity::Message) -> Result<(), Error> {
+    let incoming = Incoming {
+        author: message.author.id,
+        channel: message.channel_id,
+    };
+    let messages = listener::fetch_history(ctx.discord(), &message).await?;
+    let recent = listener::to_recent(ctx.discord(), &messages);
+    let history = recent.iter().map(Recent::as_past).collect::<Vec<_>>();
+    dry_run(ctx, incoming, &history).await
+}
+
+/// runs the steps of the listener on a message and replies with every outcome,
+/// nothing is recorded or sent to the channel
+async fn dry_run(ctx: Context<'_>, incoming: Incoming, history: &[Past<'_>]) -> Result<(), Error> {
+    let report = match_report(
+        ctx.data(),
+        ctx.guild_id().unwrap(),
+        &incoming,
+        history,
+        *BOT_ID.get().unwrap(),
+        SystemTime::now(),
+        fastrand::Rng::new(),
+    )
+    .await?;
+
+    ctx.send(|r| {
+        r.ephemeral(true);
+        r.embed(|e| {
+            e.color(ctx.data().settings.embed_color);
+            e.title("Test match");
+            if report.scores.is_empty() {
+                e.description("this guild has no catchphrases");
+            } else {
+                e.description(report.scores);
+            }
+            e.field("threshold:", report.threshold, true);
+            e.field("chance:", format!("{}%", report.chance), true);
+            e.field("channel, cooldowns and chance:", report.gate, false);
+            e.field("recent messages:", report.history, false);
+            e.field("selection:", report.selection, false);
+            e.field("query:", format!("```\n{}\n```", report.query), false)
+        })
+    })
+    .await?;
+    Ok(())
+}
+
+/// the outcome of every step of the listener for a message
+struct MatchReport {
+    /// a line per phrase with its score and cooldown
+    scores: String,
+    threshold: u16,
+    chance: u8,
+    gate: String,
+    history: String,
+    selection: String,
+    /// the query, shortened to fit an embed field
+    query: String,
+}
+
+/// runs the steps of the listener on a message without recording a response
+async fn match_report(
+    data: &Data,
+    guild_id: GuildId,
+    incoming: &Incoming,
+    history: &[Past<'_>],
+    bot_id: UserId,
+    now: SystemTime,
+    rng: fastrand::Rng,
+) -> Result<MatchReport, Error> {
+    let guild_meta_lock = data.get_guild(guild_id).await.expect("guild not found");
+    let mut guild_meta = guild_meta_lock.write().await;
+
+    let outcome = |result: Result<(), Skip>| match result {
+        Ok(()) => "passes".to_owned(),
+        Err(skip) => format!("blocked, {skip}"),
+    };
+
+    let gate = outcome(engine::gate(&guild_meta, incoming, bot_id, now, &rng));
+    let history_check = outcome(engine::check_history(history));
+
+    let config = guild_meta.channel_config(incoming.channel);
+    let query = engine::build_query(history, config.max_context_len);
+    let hits = matcher::score(data, &mut guild_meta, &query).await?;
+
+    // embed descriptions are limited to 4096 characters
+    let mut len = 0;
+    let scores = hits
+        .iter()
+        .map(|hit| {
+            let mark = if hit.score >= config.minimum_score as f64 {
+                "✅"
+            } else {
+                "❌"
+            };
+            let cooldown = engine::phrase_cooldown(&guild_meta, &config, &hit.phrase, now)
+                .map(|remaining| format!(", on cooldown for {remaining}s"))
+                .unwrap_or_default();
+            format!("{mark} `{:.2}` {}{cooldown}\n", hit.score, hit.phrase)
+        })
+        .take_while(|line| {
+            len += line.chars().count();
+            len < 4000
+        })
+        .collect::<String>();
+    let selection = match engine::select(&guild_meta, incoming.channel, hits, now, &rng) {
+        Ok(hit) => format!("would respond with `{}`", hit.phrase),
+        Err(skip) => format!("no response, {skip}"),
+    };
+
+    Ok(MatchReport {
+        scores,
+        threshold: config.minimum_score,
+        chance: config.chance,
+        gate,
+        history: history_check,
+        selection,
+        query: query.chars().take(1000).collect(),
+    })
+}
+
+/// Shows the cooldowns of this channel and the trigger budget of a user
+#[poise::command(slash_command)]
+pub async fn status(
+    ctx: Context<'_>,
+    #[description = "whose budget to show, yours if empty"] user: Option<serenity::User>,
+) -> Result<(), Error> {
+    let data = ctx.data();
+    let user = user.as_ref().unwrap_or_else(|| ctx.author());
+
+    let guild_meta_lock = data
+        .get_guild(ctx.guild_id().unwrap())
+        .await
+        .expect("guild not found");
+    let guild_meta = guild_meta_lock.read().await;
+
+    let now = SystemTime::now();
+    let config = guild_meta.channel_config(ctx.channel_id());
+    let format_remaining = |remaining: Option<u64>| match remaining {
+        Some(remaining) => format!("{remaining}s left"),
+        None => "ready".to_owned(),
+    };
+
+    let channel = if guild_meta.channels.contains_key(&ctx.channel_id()) {
+        format_remaining(engine::channel_cooldown(
+            &guild_meta,
+            &config,
+            ctx.channel_id(),
+            now,
+        ))
+    } else {
+        "channel is not registered".to_owned()
+    };
+    let guild = match config.guild_cooldown {
+        Some(_) => format_remaining(engine::guild_cooldown(&guild_meta, &config, now)),
+        None => "none".to_owned(),
+    };
+    let budget = engine::user_budget(&guild_meta, &config, user.id, now);
+    let budget = match (budget.limit, budget.reset) {
+        (Some(limit), Some(reset)) => format!(
+            "{} of {limit} responses used, one frees up in {reset}s",
+            budget.used
+        ),
+        (Some(limit), None) => format!("0 of {limit} responses used"),
+        (None, _) => format!("{} responses triggered, no limit", budget.used),
+    };
+
+    ctx.send(|r| {
+        r.embed(|e| {
+            e.color(ctx.data().settings.embed_color);
+            e.title("Status");
+            e.field("channel cooldown:", channel, true);
+            e.field("guild cooldown:", guild, true);
+            e.field(
+                format!("budget of {}:", user.name),
+                format!("{budget}\nwindow: {}s", config.user_budget_window),
+                false,
+            )
+        })
+    })
+    .await?;
+    Ok(())
+}
+
+/// Sets how long a catchphrase waits before it is repeated
+#[poise::command(slash_command, check = "manage_phrases")]
+pub async fn set_phrase_cooldown(
+    ctx: Context<'_>,
+    #[description = "the catchphrase"] phrase: String,
+    #[description = "seconds before it is repeated, leave empty for the guild default"]
+    cooldown: Option<u32>,
+) -> Result<(), Error> {
+    let data = ctx.data();
+
+    let guild_meta_lock = data
+        .get_guild(ctx.guild_id().unwrap())
+        .await
+        .expect("guild not found");
+    let mut guild_meta = guild_meta_lock.write().await;
+
+    let Some(entry) = guild_meta.phrases.get_mut(&phrase) else {
+        ctx.send(|r| {
+            r.embed(|e| {
+                e.color(ctx.data().settings.embed_color);
+                e.title("Phrase cooldown");
+                e.field("error: ", "phrase not found", true)
+            })
+        })
+        .await?;
+        return Ok(());
+    };
+    entry.cooldown = cooldown;
+    data.mark_dirty(ctx.guild_id().unwrap());
+
+    let cooldown = match cooldown {
+        Some(cooldown) => format!("{cooldown}s"),
+        None => format!("guild default ({}s)", guild_meta.config.phrase_cooldown),
+    };
+    ctx.send(|r| {
+        r.embed(|e| {
+            e.color(ctx.data().settings.embed_color);
+            e.title("Phrase cooldown");
+            e.field("phrase: ", &phrase, true);
+            e.field("cooldown: ", cooldown, true)
+        })
+    })
+    .await?;
+    Ok(())
+}
+/// shows the bot config as it applies to this channel
+#[poise::command(slash_command, check = "manage_config")]
 pub async fn show_config(ctx: Context<'_>) -> Result<(), Error> {
     let data = ctx.data();
 
@@ -148,30 +797,83 @@ pub async fn show_config(ctx: Context<'_>) -> Result<(), Error> {
         .expect("guild not found");
     let guild_meta = guild_meta_lock.read().await;
 
-    let config = &guild_meta.config;
-    let config = ron::ser::to_string_pretty(config, PrettyConfig::default())?;
+    let config = guild_meta.channel_config(ctx.channel_id());
+    let cost = config.backend.matcher().cost(&config);
+    let config = ron::ser::to_string_pretty(&config, PrettyConfig::default())?;
+    let overrides = guild_meta
+        .channels
+        .get(&ctx.channel_id())
+        .map(|channel| {
+            channel
+                .overrides
+                .iter()
+                .map(|(key, value)| format!("`{key}`: {value}"))
+                .intersperse("\n".to_owned())
+                .collect::<String>()
+        })
+        .unwrap_or_default();
 
     ctx.send(|r| {
         r.embed(|e| {
-            e.color(EMBED_COLOR);
+            e.color(ctx.data().settings.embed_color);
             e.title("Config");
-            e.field("config values:", format!("```\n{config}\n```"), false)
+            e.field("config values:", format!("```\n{config}\n```"), false);
+            if !overrides.is_empty() {
+                e.field("overridden in this channel:", overrides, false);
+            }
+            e.field("cost per match:", cost, false)
         })
     })
     .await?;
     Ok(())
 }
 /// loads the bot config
-#[poise::command(slash_command, owners_only)]
+#[poise::command(slash_command, check = "manage_config")]
 pub async fn load_config(
     ctx: Context<'_>,
     #[description = "the config to load"] config: String,
 ) -> Result<(), Error> {
     let data = ctx.data();
 
-    let new_config = ron::from_str(&config);
+    let new_config = ron::from_str::<Config>(&config);
 
     if let Ok(new_config) = new_config {
+        let matcher = new_config.backend.matcher();
+
+        if let Err(err) = new_config.validate() {
+            ctx.send(|r| {
+                r.embed(|e| {
+                    e.color(ctx.data().settings.embed_color);
+                    e.title("Edit config");
+                    e.field("config values:", err, true)
+                })
+            })
+            .await?;
+            return Ok(());
+        }
+
+        // reject models the backend can't match with
+        if !matcher.supports(&new_config) {
+            ctx.send(|r| {
+                r.embed(|e| {
+                    e.color(ctx.data().settings.embed_color);
+                    e.title("Edit config");
+                    e.field(
+                        "config values:",
+                        format!(
+                            "model `{}` is not supported by backend `{:?}`",
+                            model_name(&new_config.model),
+                            new_config.backend
+                        ),
+                        true,
+                    )
+                })
+            })
+            .await?;
+            return Ok(());
+        }
+        let cost = matcher.cost(&new_config);
+
         let guild_meta_lock = data
             .get_guild(ctx.guild_id().unwrap())
             .await
@@ -181,16 +883,19 @@ pub async fn load_config(
         guild_meta.config = new_config;
         ctx.send(|r| {
             r.embed(|e| {
-                e.color(EMBED_COLOR);
+                e.color(ctx.data().settings.embed_color);
                 e.title("Edit config");
-                e.field("config values:", config, false)
+                e.field("config values:", config, false);
+                e.field("cost per match:", cost, false)
             })
         })
         .await?;
+        index_phrases(data, &mut guild_meta).await;
+        data.mark_dirty(ctx.guild_id().unwrap());
     } else {
         ctx.send(|r| {
             r.embed(|e| {
-                e.color(EMBED_COLOR);
+                e.color(ctx.data().settings.embed_color);
                 e.title("Edit config");
                 e.field("config values:", "invalid format", true)
             })
@@ -200,6 +905,177 @@ pub async fn load_config(
     Ok(())
 }
 
+/// edits single config values
+#[poise::command(
+    slash_command,
+    subcommands("config_set", "config_reset"),
+    check = "manage_config"
+)]
+pub async fn config(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// suggests config keys
+async fn autocomplete_key(_ctx: Context<'_>, partial: String) -> impl Iterator<Item = String> {
+    Config::KEYS
+        .iter()
+        .filter(move |key| key.starts_with(&partial))
+        .map(|key| key.to_string())
+}
+
+/// sets a config value
+#[poise::command(slash_command, rename = "set", check = "manage_config")]
+pub async fn config_set(
+    ctx: Context<'_>,
+    #[description = "the config key"]
+    #[autocomplete = "autocomplete_key"]
+    key: String,
+    #[description = "the new value"] value: String,
+    #[description = "only set it for this channel"] channel: Option<bool>,
+) -> Result<(), Error> {
+    edit_config(ctx, &key, Some(&value), channel.unwrap_or_default()).await
+}
+
+/// resets a config value to its default, or a channel value to the guild value
+#[poise::command(slash_command, rename = "reset", check = "manage_config")]
+pub async fn config_reset(
+    ctx: Context<'_>,
+    #[description = "the config key"]
+    #[autocomplete = "autocomplete_key"]
+    key: String,
+    #[description = "only reset the override of this channel"] channel: Option<bool>,
+) -> Result<(), Error> {
+    edit_config(ctx, &key, None, channel.unwrap_or_default()).await
+}
+
+/// sets or resets a value of the guild config or of the overrides of this channel
+/// and replies with the changed values
+async fn edit_config(
+    ctx: Context<'_>,
+    key: &str,
+    value: Option<&str>,
+    channel: bool,
+) -> Result<(), Error> {
+    let result = update_config(
+        ctx.data(),
+        ctx.guild_id().unwrap(),
+        channel.then(|| ctx.channel_id()),
+        key,
+        value,
+    )
+    .await;
+
+    let change = match result {
+        Ok(change) => change,
+        Err(err) => {
+            ctx.send(|r| {
+                r.embed(|e| {
+                    e.color(ctx.data().settings.embed_color);
+                    e.title("Edit config");
+                    e.field("error:", err, true)
+                })
+            })
+            .await?;
+            return Ok(());
+        }
+    };
+
+    ctx.send(|r| {
+        r.embed(|e| {
+            e.color(ctx.data().settings.embed_color);
+            if channel {
+                e.title("Edit channel config");
+            } else {
+                e.title("Edit config");
+            }
+            if change.changes.is_empty() {
+                e.field("changes:", "nothing changed", false);
+            } else {
+                e.field("changes:", change.changes.join("\n"), false);
+            }
+            e.field("cost per match:", change.cost, false)
+        })
+    })
+    .await?;
+    Ok(())
+}
+
+/// the values changed by a config edit
+struct ConfigChange {
+    /// lines like `` `key`: before → after ``
+    changes: Vec<String>,
+    /// what a match costs with the edited config
+    cost: String,
+}
+
+/// sets a config value, or resets it when there is no value,
+/// of the guild or of the overrides of a channel
+async fn update_config(
+    data: &Data,
+    guild_id: GuildId,
+    channel: Option<ChannelId>,
+    key: &str,
+    value: Option<&str>,
+) -> Result<ConfigChange, String> {
+    let guild_meta_lock = data.get_guild(guild_id).await.expect("guild not found");
+    let mut guild_meta = guild_meta_lock.write().await;
+
+    let before = match channel {
+        Some(channel_id) => guild_meta.channel_config(channel_id),
+        None => guild_meta.config.clone(),
+    };
+    let mut config = before.clone();
+    if let Some(channel_id) = channel {
+        if !guild_meta.channels.contains_key(&channel_id) {
+            return Err("this channel is not registered".to_owned());
+        }
+        if !Config::is_channel_key(key) {
+            return Err(format!("`{key}` can only be set for the whole guild"));
+        }
+    }
+    match value {
+        Some(value) => config.set(key, value)?,
+        // channels fall back to the guild value
+        None if channel.is_some() => config.reset(key, &guild_meta.config)?,
+        None => config.reset(key, &data.settings.guild_defaults)?,
+    }
+    // reject models the backend can't match with
+    if !config.backend.matcher().supports(&config) {
+        return Err(format!(
+            "model `{}` is not supported by backend `{:?}`",
+            model_name(&config.model),
+            config.backend
+        ));
+    }
+
+    let changes = Config::KEYS
+        .iter()
+        .filter_map(|key| {
+            let before = before.get(key)?;
+            let after = config.get(key)?;
+            (before != after).then(|| format!("`{key}`: {before} → {after}"))
+        })
+        .collect::<Vec<_>>();
+    let cost = config.backend.matcher().cost(&config);
+
+    match channel {
+        Some(channel_id) => {
+            let overrides = &mut guild_meta.channels.get_mut(&channel_id).unwrap().overrides;
+            match config.get(key).filter(|_| value.is_some()) {
+                Some(value) => overrides.insert(key.to_owned(), value),
+                None => overrides.remove(key),
+            };
+        }
+        None => {
+            guild_meta.config = config;
+            index_phrases(data, &mut guild_meta).await;
+        }
+    }
+    data.mark_dirty(guild_id);
+
+    Ok(ConfigChange { changes, cost })
+}
+
 /// registers slash commands
 #[poise::command(prefix_command, owners_only)]
 pub async fn register(ctx: Context<'_>) -> Result<(), Error> {
@@ -208,7 +1084,7 @@ pub async fn register(ctx: Context<'_>) -> Result<(), Error> {
 }
 
 /// add this channel
-#[poise::command(slash_command, owners_only)]
+#[poise::command(slash_command, check = "manage_channels")]
 pub async fn add_channel(ctx: Context<'_>) -> Result<(), Error> {
     let data = ctx.data();
 
@@ -220,13 +1096,14 @@ pub async fn add_channel(ctx: Context<'_>) -> Result<(), Error> {
 
     let channels = &mut guild_meta.channels;
     let channel = ctx.channel_id();
-    channels.insert(channel);
+    channels.entry(channel).or_default();
+    data.mark_dirty(ctx.guild_id().unwrap());
     ctx.say("added this channel").await?;
     Ok(())
 }
 
 /// removes this channel
-#[poise::command(slash_command, owners_only)]
+#[poise::command(slash_command, check = "manage_channels")]
 pub async fn remove_channel(ctx: Context<'_>) -> Result<(), Error> {
     let data = ctx.data();
 
@@ -239,6 +1116,7 @@ pub async fn remove_channel(ctx: Context<'_>) -> Result<(), Error> {
     let channels = &mut guild_meta.channels;
     let channel = ctx.channel_id();
     channels.remove(&channel);
+    data.mark_dirty(ctx.guild_id().unwrap());
     ctx.say("removed this channel").await?;
     Ok(())
 }
@@ -248,30 +1126,11 @@ pub async fn remove_channel(ctx: Context<'_>) -> Result<(), Error> {
 pub async fn dump_configs(ctx: Context<'_>) -> Result<(), Error> {
     let data = ctx.data();
 
-    let guild_meta_map = data.guild_meta_map.read().await;
     ctx.say("attempting to dump configs to file").await?;
 
-    let mut errors: Option<u32> = None;
-
-    for (guild_id, guild_meta_lock) in guild_meta_map.iter() {
-        let guild_id = guild_id.0;
-        let guild_meta = guild_meta_lock.read().await;
-        let text = ron::ser::to_string_pretty(&*guild_meta, Default::default())?;
-        drop(guild_meta);
-
-        match tokio::fs::write(format!("{DATA_PATH}/{guild_id}.ron"), text).await {
-            Ok(_) => {
-                debug!("dumped guild: {}", guild_id);
-            }
-            Err(err) => {
-                warn!("error while dumping guild: {}", guild_id);
-                warn!("error: `{}`", err);
-                errors = Some(errors.unwrap_or_default() + 1);
-            }
-        }
-    }
+    let errors = storage::save_all(data).await;
 
-    if let Some(errors) = errors {
+    if errors > 0 {
         ctx.say(format!("failed to dump {} guilds", errors)).await?;
     } else {
         ctx.say("dumped configs succesfully").await?;
@@ -280,7 +1139,7 @@ pub async fn dump_configs(ctx: Context<'_>) -> Result<(), Error> {
 }
 
 /// loads phrases from serializable format
-#[poise::command(slash_command, owners_only)]
+#[poise::command(slash_command, check = "manage_phrases")]
 pub async fn load_phrases(
     ctx: Context<'_>,
     #[description = "the phrases to load"] phrases: String,
@@ -295,12 +1154,468 @@ pub async fn load_phrases(
             .expect("guild not found");
         let mut guild_meta = guild_meta_lock.write().await;
 
-        for phrase in phrases_loaded.drain() {
-            guild_meta.phrases.insert(phrase, Default::default());
+        // phrases that aren't valid templates are skipped
+        let (valid, invalid): (Vec<_>, Vec<_>) = phrases_loaded
+            .drain()
+            .partition(|phrase| templates::validate(phrase).is_ok());
+        // phrases that already exist keep their variants and settings
+        for phrase in valid {
+            guild_meta.phrases.entry(phrase).or_default();
+        }
+        index_phrases(data, &mut guild_meta).await;
+        data.mark_dirty(ctx.guild_id().unwrap());
+        if invalid.is_empty() {
+            ctx.say("loaded phrases").await?;
+        } else {
+            let invalid = invalid
+                .iter()
+                .map(|phrase| format!("`{phrase}`"))
+                .intersperse(", ".to_owned())
+                .collect::<String>();
+            ctx.say(format!(
+                "loaded phrases, skipped invalid templates: {invalid}"
+            ))
+            .await?;
         }
-        ctx.say("loaded phrases").await?;
     } else {
         ctx.say("error loading phrases").await?;
     }
     Ok(())
 }
+
+/// deletes the stored data of this guild and resets it to defaults
+#[poise::command(slash_command, owners_only)]
+pub async fn reset_guild(ctx: Context<'_>) -> Result<(), Error> {
+    let data = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+
+    let guild_meta_lock = data.get_guild(guild_id).await.expect("guild not found");
+    let mut guild_meta = guild_meta_lock.write().await;
+
+    data.storage.delete(guild_id).await?;
+    *guild_meta = data.new_guild();
+
+    ctx.say("reset this guild").await?;
+    Ok(())
+}
+
+/// shows who may manage this guild
+#[poise::command(slash_command, check = "manage_access")]
+pub async fn show_access(ctx: Context<'_>) -> Result<(), Error> {
+    let data = ctx.data();
+
+    let guild_meta_lock = data
+        .get_guild(ctx.guild_id().unwrap())
+        .await
+        .expect("guild not found");
+    let guild_meta = guild_meta_lock.read().await;
+
+    let areas = [Area::Phrases, Area::Config, Area::Channels].map(|area| {
+        let access = guild_meta.access.get(area);
+        let roles = access
+            .roles
+            .iter()
+            .map(|role| role.mention().to_string())
+            .intersperse(", ".to_owned())
+            .collect::<String>();
+        (
+            format!("{:?}", area).to_lowercase(),
+            format!("roles: {}\npermissions: {}", roles, access.permissions),
+            false,
+        )
+    });
+
+    ctx.send(|r| {
+        r.embed(|e| {
+            e.color(ctx.data().settings.embed_color);
+            e.title("Access");
+            e.fields(areas)
+        })
+    })
+    .await?;
+    Ok(())
+}
+
+/// allows a role to manage an area
+#[poise::command(slash_command, check = "manage_access")]
+pub async fn grant_role(
+    ctx: Context<'_>,
+    #[description = "the commands to allow"] area: Area,
+    #[description = "the role to allow"] role: serenity::Role,
+) -> Result<(), Error> {
+    let data = ctx.data();
+
+    let guild_meta_lock = data
+        .get_guild(ctx.guild_id().unwrap())
+        .await
+        .expect("guild not found");
+    let mut guild_meta = guild_meta_lock.write().await;
+
+    guild_meta.access.get_mut(area).roles.insert(role.id);
+    data.mark_dirty(ctx.guild_id().unwrap());
+
+    ctx.say(format!("{} may now manage {:?}", role.name, area))
+        .await?;
+    Ok(())
+}
+
+/// stops allowing a role to manage an area
+#[poise::command(slash_command, check = "manage_access")]
+pub async fn revoke_role(
+    ctx: Context<'_>,
+    #[description = "the commands to disallow"] area: Area,
+    #[description = "the role to disallow"] role: serenity::Role,
+) -> Result<(), Error> {
+    let data = ctx.data();
+
+    let guild_meta_lock = data
+        .get_guild(ctx.guild_id().unwrap())
+        .await
+        .expect("guild not found");
+    let mut guild_meta = guild_meta_lock.write().await;
+
+    if guild_meta.access.get_mut(area).roles.remove(&role.id) {
+        data.mark_dirty(ctx.guild_id().unwrap());
+        ctx.say(format!("{} may no longer manage {:?}", role.name, area))
+            .await?;
+    } else {
+        ctx.say(format!(
+            "{} was not allowed to manage {:?}",
+            role.name, area
+        ))
+        .await?;
+    }
+    Ok(())
+}
+
+/// sets the discord permissions that allow managing an area
+#[poise::command(slash_command, check = "manage_access")]
+pub async fn set_access_permissions(
+    ctx: Context<'_>,
+    #[description = "the commands to change"] area: Area,
+    #[description = "permission bits, any of them allows access, 0 for roles only"]
+    permissions: u64,
+) -> Result<(), Error> {
+    let data = ctx.data();
+
+    let guild_meta_lock = data
+        .get_guild(ctx.guild_id().unwrap())
+        .await
+        .expect("guild not found");
+    let mut guild_meta = guild_meta_lock.write().await;
+
+    let permissions = serenity::Permissions::from_bits_truncate(permissions);
+    guild_meta.access.get_mut(area).permissions = permissions;
+    data.mark_dirty(ctx.guild_id().unwrap());
+
+    ctx.say(format!(
+        "{:?} may now be managed with: {}",
+        area, permissions
+    ))
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use poise::serenity_prelude::RwLock;
+
+    use super::*;
+    use crate::{data::Channel, matcher::Backend, testing};
+
+    const GUILD: GuildId = GuildId(1);
+    const CHANNEL: ChannelId = ChannelId(2);
+    const OTHER_CHANNEL: ChannelId = ChannelId(3);
+    const AUTHOR: UserId = UserId(10);
+    const BOT: UserId = UserId(20);
+
+    /// a bot with a registered guild that matches locally
+    async fn registered(mut guild_meta: GuildMeta) -> (Data, Arc<RwLock<GuildMeta>>) {
+        let data = testing::data();
+        guild_meta.config.backend = Backend::Bm25;
+        let guild_meta = Arc::new(RwLock::new(guild_meta));
+        data.guild_meta_map
+            .write()
+            .await
+            .insert(GUILD, guild_meta.clone());
+        (data, guild_meta)
+    }
+    fn with_channel() -> GuildMeta {
+        let mut guild_meta = testing::guild_meta();
+        guild_meta.channels.insert(CHANNEL, Channel::default());
+        guild_meta
+    }
+
+    #[tokio::test]
+    async fn adds_phrases_keeping_their_settings() {
+        let (data, guild_meta) = registered(testing::guild_meta()).await;
+
+        let keywords = vec!["high ground".to_owned()];
+        insert_phrase(&data, GUILD, "it's over anakin", keywords)
+            .await
+            .unwrap();
+        assert!(guild_meta.read().await.phrases["it's over anakin"]
+            .keywords
+            .contains("high ground"));
+        assert_eq!(data.take_dirty(), [GUILD]);
+
+        // adding a phrase again only replaces its keywords
+        let keywords = vec!["hi".to_owned()];
+        insert_phrase(&data, GUILD, "general kenobi", keywords)
+            .await
+            .unwrap();
+        let guild_meta = guild_meta.read().await;
+        let entry = &guild_meta.phrases["general kenobi"];
+        assert_eq!(entry.keywords, HashSet::from(["hi".to_owned()]));
+        assert_eq!(entry.variants.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_templates() {
+        let (data, guild_meta) = registered(GuildMeta::default()).await;
+
+        let result = insert_phrase(&data, GUILD, "hello {there}", Vec::new()).await;
+        assert!(result.is_err());
+        assert!(guild_meta.read().await.phrases.is_empty());
+        assert!(data.take_dirty().is_empty());
+    }
+
+    #[tokio::test]
+    async fn edits_phrases_and_restarts_their_rotation() {
+        let (data, guild_meta) = registered(testing::guild_meta()).await;
+        guild_meta
+            .write()
+            .await
+            .phrases
+            .get_mut("general kenobi")
+            .unwrap()
+            .rotation = vec![2, 1];
+
+        let change = update_phrase(&data, GUILD, "general kenobi", |entry| {
+            entry.variants.pop();
+            Ok("removed a variant".to_owned())
+        })
+        .await;
+        assert_eq!(change.as_deref(), Ok("removed a variant"));
+        let guild_meta = guild_meta.read().await;
+        let entry = &guild_meta.phrases["general kenobi"];
+        assert_eq!(entry.variants.len(), 1);
+        assert!(entry.rotation.is_empty());
+        assert_eq!(data.take_dirty(), [GUILD]);
+    }
+
+    #[tokio::test]
+    async fn failed_phrase_edits_change_nothing() {
+        let (data, guild_meta) = registered(testing::guild_meta()).await;
+        guild_meta
+            .write()
+            .await
+            .phrases
+            .get_mut("general kenobi")
+            .unwrap()
+            .rotation = vec![2, 1];
+
+        let change = update_phrase(&data, GUILD, "general kenobi", |_| {
+            Err("variant not found".to_owned())
+        })
+        .await;
+        assert_eq!(change.as_deref(), Err("variant not found"));
+        let change = update_phrase(&data, GUILD, "hello there", |_| Ok(String::new())).await;
+        assert_eq!(change.as_deref(), Err("phrase not found"));
+
+        assert_eq!(
+            guild_meta.read().await.phrases["general kenobi"].rotation,
+            [2, 1]
+        );
+        assert!(data.take_dirty().is_empty());
+    }
+
+    #[tokio::test]
+    async fn sets_and_resets_guild_config() {
+        let (data, guild_meta) = registered(with_channel()).await;
+
+        let change = update_config(&data, GUILD, None, "chance", Some("50"))
+            .await
+            .unwrap();
+        assert_eq!(change.changes, ["`chance`: 25 → 50"]);
+        assert_eq!(change.cost, "free, runs locally and ignores the model");
+        assert_eq!(guild_meta.read().await.config.chance, 50);
+        assert_eq!(data.take_dirty(), [GUILD]);
+
+        // guild values are reset to the defaults of the settings
+        let change = update_config(&data, GUILD, None, "chance", None)
+            .await
+            .unwrap();
+        assert_eq!(change.changes, ["`chance`: 50 → 25"]);
+        assert_eq!(guild_meta.read().await.config.chance, 25);
+
+        let change = update_config(&data, GUILD, None, "chance", Some("25"))
+            .await
+            .unwrap();
+        assert!(change.changes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn sets_and_resets_channel_overrides() {
+        let (data, guild_meta) = registered(with_channel()).await;
+
+        let change = update_config(&data, GUILD, Some(CHANNEL), "chance", Some("80"))
+            .await
+            .unwrap();
+        assert_eq!(change.changes, ["`chance`: 25 → 80"]);
+        {
+            let guild_meta = guild_meta.read().await;
+            assert_eq!(guild_meta.channels[&CHANNEL].overrides["chance"], "80");
+            assert_eq!(guild_meta.config.chance, 25);
+        }
+
+        // channels are reset to the guild value
+        let change = update_config(&data, GUILD, Some(CHANNEL), "chance", None)
+            .await
+            .unwrap();
+        assert_eq!(change.changes, ["`chance`: 80 → 25"]);
+        assert!(guild_meta.read().await.channels[&CHANNEL]
+            .overrides
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_config_edits() {
+        let (data, _guild_meta) = registered(with_channel()).await;
+
+        for (channel, key, value, error) in [
+            (
+                Some(OTHER_CHANNEL),
+                "chance",
+                Some("50"),
+                "this channel is not registered",
+            ),
+            (
+                Some(CHANNEL),
+                "backend",
+                Some("Search"),
+                "`backend` can only be set for the whole guild",
+            ),
+            (None, "volume", Some("11"), "unknown key `volume`"),
+        ] {
+            let result = update_config(&data, GUILD, channel, key, value).await;
+            assert_eq!(result.err().as_deref(), Some(error));
+        }
+        assert!(data.take_dirty().is_empty());
+    }
+
+    #[tokio::test]
+    async fn reports_matches_without_recording() {
+        let (data, guild_meta) = registered(with_channel()).await;
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let incoming = Incoming {
+            author: AUTHOR,
+            channel: CHANNEL,
+        };
+        let history = [Past {
+            content: "well hello there",
+            own: false,
+        }];
+
+        let report = match_report(
+            &data,
+            GUILD,
+            &incoming,
+            &history,
+            BOT,
+            now,
+            fastrand::Rng::with_seed(0),
+        )
+        .await
+        .unwrap();
+        assert!(report.scores.starts_with("✅"), "{}", report.scores);
+        assert!(report.scores.contains("general kenobi"));
+        assert_eq!(report.history, "passes");
+        assert_eq!(report.selection, "would respond with `general kenobi`");
+        assert_eq!(report.query, "well hello there");
+        assert_eq!((report.threshold, report.chance), (20, 25));
+
+        let guild_meta = guild_meta.read().await;
+        assert!(guild_meta.cooldown.is_empty());
+        assert!(guild_meta.last_response.is_empty());
+        assert_eq!(guild_meta.phrases["general kenobi"].uses, 0);
+        assert!(data.take_dirty().is_empty());
+    }
+
+    #[tokio::test]
+    async fn reports_blocking_gates() {
+        let (data, _guild_meta) = registered(with_channel()).await;
+        let incoming = Incoming {
+            author: AUTHOR,
+            channel: OTHER_CHANNEL,
+        };
+        let history = [
+            Past {
+                content: "general kenobi",
+                own: true,
+            },
+            Past {
+                content: "hello there",
+                own: false,
+            },
+        ];
+
+        let report = match_report(
+            &data,
+            GUILD,
+            &incoming,
+            &history,
+            BOT,
+            SystemTime::now(),
+            fastrand::Rng::with_seed(0),
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.gate, "blocked, channel is not registered");
+        assert_eq!(
+            report.history,
+            "blocked, bot is part of the recent messages"
+        );
+    }
+
+    #[test]
+    fn accepts_emoji() {
+        for emoji in [
+            "👍",
+            "❤️",
+            "👍🏽",
+            "🇺🇸",
+            "👨‍👩‍👧",
+            "1️⃣",
+            "#⃣",
+            "©️",
+            "<:kenobi:123456789012345678>",
+            "<a:dance:123456789012345678>",
+        ] {
+            assert!(is_emoji(emoji), "`{emoji}` was rejected");
+        }
+    }
+
+    #[test]
+    fn rejects_text() {
+        for text in [
+            ":)",
+            "123",
+            "!!",
+            "->",
+            "hello",
+            "a👍",
+            "12⃣",
+            "<:kenobi>",
+            "",
+            "\u{FE0F}",
+        ] {
+            assert!(!is_emoji(text), "`{text}` was accepted");
+        }
+    }
+}
@@ -1,16 +1,46 @@
 use std::{
-    collections::{HashMap, HashSet},
-    sync::Arc,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Display,
+    ops::RangeInclusive,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::SystemTime,
 };
 
-use gpt3_rs::Client;
-use poise::serenity_prelude::{ChannelId, GuildId, RwLock};
+use log::warn;
+use poise::serenity_prelude::{ChannelId, GuildId, RwLock, StickerId, UserId};
 use serde::{Deserialize, Serialize};
-use tokio::time::Instant;
+use tokio::sync::{Mutex as AsyncMutex, Notify};
+
+use crate::{
+    engine::Selection,
+    matcher::{Backend, EmbeddingIndex},
+    openai,
+    permissions::AccessControl,
+    settings::Settings,
+    storage::Storage,
+};
 
 pub struct Data {
-    pub client: Client,
+    pub client: openai::Client,
+    pub settings: Settings,
+    pub storage: Box<dyn Storage>,
     pub guild_meta_map: RwLock<HashMap<GuildId, Arc<RwLock<GuildMeta>>>>,
+    /// guilds changed since they were last saved
+    pub dirty: Mutex<HashSet<GuildId>>,
+    /// notified whenever a guild is marked as dirty
+    pub changed: Notify,
+    /// held while saving, so saves never overlap and a flush waits for a running save
+    pub saving: AsyncMutex<()>,
+    /// set once the bot shuts down, no new events are handled after that
+    pub shutting_down: AtomicBool,
+    /// number of events currently being handled
+    pub in_flight: AtomicUsize,
+    /// notified when the last in-flight event finishes
+    pub idle: Notify,
 }
 impl Data {
     /// regrieves a guild from the guild map
@@ -18,47 +48,533 @@ impl Data {
         let guild_meta_map = self.guild_meta_map.read().await;
         guild_meta_map.get(&guild_id).cloned()
     }
+    /// creates a guild with the configured defaults
+    pub fn new_guild(&self) -> GuildMeta {
+        GuildMeta {
+            config: self.settings.guild_defaults.clone(),
+            ..Default::default()
+        }
+    }
+    /// marks a guild as changed so it gets saved
+    pub fn mark_dirty(&self, guild_id: GuildId) {
+        self.dirty.lock().unwrap().insert(guild_id);
+        self.changed.notify_one();
+    }
+    /// takes every guild marked as changed
+    pub fn take_dirty(&self) -> Vec<GuildId> {
+        self.dirty.lock().unwrap().drain().collect()
+    }
+    /// tracks an event until the guard is dropped, returns `None` when shutting down
+    pub fn begin_event(&self) -> Option<EventGuard<'_>> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let guard = EventGuard { data: self };
+
+        if self.is_shutting_down() {
+            None
+        } else {
+            Some(guard)
+        }
+    }
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+    /// rejects every event from now on
+    pub fn stop_events(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+    }
+    /// whether no events are in flight
+    pub fn is_idle(&self) -> bool {
+        self.in_flight.load(Ordering::SeqCst) == 0
+    }
+    /// waits until no events are in flight
+    pub async fn drained(&self) {
+        loop {
+            let idle = self.idle.notified();
+            if self.in_flight.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            idle.await;
+        }
+    }
+}
+
+/// marks an event as in flight while alive
+pub struct EventGuard<'a> {
+    data: &'a Data,
+}
+impl Drop for EventGuard<'_> {
+    fn drop(&mut self) {
+        if self.data.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.data.idle.notify_waiters();
+        }
+    }
 }
 #[derive(Default, Serialize, Deserialize)]
 pub struct GuildMeta {
-    #[serde(skip)]
-    pub last_response: Option<Instant>,
-    #[serde(skip)]
-    pub cooldown: HashMap<String, Instant>,
-    pub phrases: HashMap<String, HashSet<String>>,
-    pub channels: HashSet<ChannelId>,
+    /// last response in each channel
+    #[serde(default)]
+    pub last_response: HashMap<ChannelId, SystemTime>,
+    /// last use of each phrase
+    #[serde(default)]
+    pub cooldown: HashMap<String, SystemTime>,
+    /// responses triggered by each user, within the budget window
+    #[serde(default)]
+    pub triggers: HashMap<UserId, Vec<SystemTime>>,
+    pub phrases: HashMap<String, Phrase>,
+    pub channels: HashMap<ChannelId, Channel>,
     pub config: Config,
+    #[serde(default)]
+    pub embeddings: EmbeddingIndex,
+    #[serde(default)]
+    pub access: AccessControl,
+}
+impl GuildMeta {
+    /// the guild config with the overrides of a channel applied
+    pub fn channel_config(&self, channel_id: ChannelId) -> Config {
+        let mut config = self.config.clone();
+        if let Some(channel) = self.channels.get(&channel_id) {
+            for (key, value) in &channel.overrides {
+                if let Err(err) = config.set(key, value) {
+                    warn!("ignoring override of channel {}: {}", channel_id, err);
+                }
+            }
+        }
+        config
+    }
+    /// longest user budget window of the guild and its channels
+    ///
+    /// triggers are kept this long, so no channel loses triggers it still counts
+    pub fn longest_budget_window(&self) -> u32 {
+        self.channels
+            .keys()
+            .map(|channel_id| self.channel_config(*channel_id).user_budget_window)
+            .fold(self.config.user_budget_window, u32::max)
+    }
+    /// last response in any channel
+    pub fn last_guild_response(&self) -> Option<SystemTime> {
+        self.last_response.values().max().copied()
+    }
 }
 
-#[derive(Serialize, Deserialize)]
+/// a registered channel
+#[derive(Default, Serialize, Deserialize)]
+pub struct Channel {
+    /// config values used instead of the guild values, formatted like `Config::get`
+    #[serde(default)]
+    pub overrides: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Config {
     pub minimum_score: u16,
     pub max_context_len: usize,
     pub chance: u8,
+    /// seconds between responses in one channel
     pub cooldown: u16,
+    /// seconds between responses in the whole guild, on top of the channel cooldown
+    #[serde(default)]
+    pub guild_cooldown: Option<u16>,
+    /// seconds before the same phrase is repeated
+    #[serde(default = "default_phrase_cooldown")]
+    pub phrase_cooldown: u32,
+    /// responses one user can trigger per budget window, unlimited if `None`
+    #[serde(default)]
+    pub user_budget: Option<u16>,
+    /// seconds the user budget applies to
+    #[serde(default = "default_user_budget_window")]
+    pub user_budget_window: u32,
+    /// send text responses as replies to the triggering message
+    #[serde(default)]
+    pub reply: bool,
+    /// whether replies ping the author of the triggering message
+    #[serde(default)]
+    pub reply_ping: bool,
+    #[serde(default)]
+    pub selection: Selection,
     pub model: gpt3_rs::Model,
+    /// model name sent to the api instead of the one derived from `model`
+    #[serde(default)]
+    pub model_name: Option<String>,
+    #[serde(default)]
+    pub backend: Backend,
 }
-#[derive(Serialize, Deserialize, PartialEq, Eq)]
+/// a catchphrase, keyed by its text in `GuildMeta::phrases`
+///
+/// the phrase is matched, the response is either the phrase itself or one of its variants
+#[derive(Default, Serialize, Deserialize, PartialEq)]
 pub struct Phrase {
-    pub phrase: String,
     pub keywords: HashSet<String>,
+    /// seconds before the phrase is repeated, replaces `Config::phrase_cooldown`
+    #[serde(default)]
+    pub cooldown: Option<u32>,
+    /// responses sent instead of the phrase itself
+    #[serde(default)]
+    pub variants: Vec<String>,
+    #[serde(default)]
+    pub mode: VariantMode,
+    /// responses left in the current rotation, 0 is the phrase and the rest index `variants` + 1
+    #[serde(default)]
+    pub rotation: Vec<usize>,
+    /// response sent last, indexed like `rotation`
+    #[serde(default)]
+    pub last_response: Option<usize>,
+    /// times the phrase was responded with
+    #[serde(default)]
+    pub uses: u64,
+    #[serde(default)]
+    pub kind: ResponseKind,
+    /// whether replies ping the author, replaces `Config::reply_ping`
+    #[serde(default)]
+    pub ping: Option<bool>,
+    /// multiplies the score of the phrase when choosing among hits, 1 if `None`
+    #[serde(default)]
+    pub weight: Option<f64>,
+}
+
+/// how a phrase is sent
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ResponseKind {
+    /// a message in the channel, sent as a reply if `Config::reply` is set
+    #[default]
+    Message,
+    /// always a reply to the triggering message
+    Reply,
+    /// reactions to the triggering message, unicode or `<:name:id>` guild emoji,
+    /// the text of the phrase is not sent
+    React(Vec<String>),
+    /// a sticker of the guild instead of the text
+    Sticker(StickerId),
+}
+
+/// how the response of a phrase is picked
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, poise::SlashChoiceParameter,
+)]
+pub enum VariantMode {
+    /// any response, repeats are possible
+    #[default]
+    #[name = "random"]
+    Random,
+    /// every response once in shuffled order before any repeats
+    #[name = "rotation"]
+    Rotation,
 }
 impl Default for Config {
     fn default() -> Self {
         Self {
             cooldown: 60,
+            guild_cooldown: None,
+            phrase_cooldown: default_phrase_cooldown(),
+            user_budget: None,
+            user_budget_window: default_user_budget_window(),
+            reply: false,
+            reply_ping: false,
+            selection: Selection::default(),
             chance: 25,
             max_context_len: 512,
             minimum_score: 20,
             model: gpt3_rs::Model::Babbage,
+            model_name: None,
+            backend: Backend::default(),
+        }
+    }
+}
+impl Config {
+    /// keys of the fields editable with `get` and `set`
+    pub const KEYS: &'static [&'static str] = &[
+        "minimum_score",
+        "max_context_len",
+        "chance",
+        "cooldown",
+        "guild_cooldown",
+        "phrase_cooldown",
+        "user_budget",
+        "user_budget_window",
+        "reply",
+        "reply_ping",
+        "selection",
+        "model",
+        "model_name",
+        "backend",
+    ];
+    /// keys only the guild can set, the phrase index of the guild is built with them
+    pub const MATCHER_KEYS: &'static [&'static str] = &["model", "model_name", "backend"];
+
+    /// whether a channel can override a key, every key but the matcher keys
+    ///
+    /// unknown keys pass, `set` rejects them with a better error
+    pub fn is_channel_key(key: &str) -> bool {
+        !Self::MATCHER_KEYS.contains(&key)
+    }
+
+    /// formats a field the same way `set` parses it
+    pub fn get(&self, key: &str) -> Option<String> {
+        let value = match key {
+            "minimum_score" => self.minimum_score.to_string(),
+            "max_context_len" => self.max_context_len.to_string(),
+            "chance" => self.chance.to_string(),
+            "cooldown" => self.cooldown.to_string(),
+            "guild_cooldown" => self
+                .guild_cooldown
+                .map_or_else(|| "none".to_owned(), |cooldown| cooldown.to_string()),
+            "phrase_cooldown" => self.phrase_cooldown.to_string(),
+            "user_budget" => self
+                .user_budget
+                .map_or_else(|| "none".to_owned(), |budget| budget.to_string()),
+            "user_budget_window" => self.user_budget_window.to_string(),
+            "reply" => self.reply.to_string(),
+            "reply_ping" => self.reply_ping.to_string(),
+            "selection" => ron::to_string(&self.selection).ok()?,
+            "model" => ron::to_string(&self.model).ok()?,
+            "model_name" => self.model_name.clone().unwrap_or_else(|| "none".to_owned()),
+            "backend" => ron::to_string(&self.backend).ok()?,
+            _ => return None,
+        };
+        Some(value)
+    }
+    /// parses and validates a value for a field
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        match key {
+            "minimum_score" => self.minimum_score = parse_range(key, value, 0..=u16::MAX)?,
+            "max_context_len" => self.max_context_len = parse_range(key, value, 1..=4096)?,
+            "chance" => self.chance = parse_range(key, value, 0..=100)?,
+            "cooldown" => self.cooldown = parse_range(key, value, 1..=u16::MAX)?,
+            "guild_cooldown" => {
+                self.guild_cooldown = match value {
+                    "" | "none" => None,
+                    value => Some(parse_range(key, value, 1..=u16::MAX)?),
+                }
+            }
+            "phrase_cooldown" => self.phrase_cooldown = parse_range(key, value, 0..=u32::MAX)?,
+            "user_budget" => {
+                self.user_budget = match value {
+                    "" | "none" => None,
+                    value => Some(parse_range(key, value, 1..=u16::MAX)?),
+                }
+            }
+            "user_budget_window" => {
+                self.user_budget_window = parse_range(key, value, 1..=u32::MAX)?
+            }
+            "reply" => self.reply = parse_bool(key, value)?,
+            "reply_ping" => self.reply_ping = parse_bool(key, value)?,
+            "selection" => {
+                let selection = ron::from_str::<Selection>(value).map_err(|_| {
+                    format!("`{key}` must be one of Argmax, Softmax(temperature: 10.0) or TopK(3)")
+                })?;
+                selection.validate()?;
+                self.selection = selection;
+            }
+            "model" => {
+                self.model = ron::from_str(value)
+                    .map_err(|_| format!("`{key}` must be one of Ada, Babbage, Curie or Davinci"))?
+            }
+            "model_name" => {
+                self.model_name = match value {
+                    "" | "none" => None,
+                    value => Some(value.to_owned()),
+                }
+            }
+            "backend" => {
+                self.backend = ron::from_str(value)
+                    .map_err(|_| format!("`{key}` must be one of Search, Bm25 or Embeddings"))?
+            }
+            _ => return Err(format!("unknown key `{key}`")),
         }
+        Ok(())
     }
+    /// validates every field like `set` would
+    pub fn validate(&self) -> Result<(), String> {
+        let mut config = self.clone();
+        for key in Self::KEYS {
+            let value = self
+                .get(key)
+                .ok_or_else(|| format!("`{key}` can't be formatted"))?;
+            config.set(key, &value)?;
+        }
+        Ok(())
+    }
+    /// resets a field to its value in `defaults`
+    pub fn reset(&mut self, key: &str, defaults: &Config) -> Result<(), String> {
+        let value = defaults
+            .get(key)
+            .ok_or_else(|| format!("unknown key `{key}`"))?;
+        self.set(key, &value)
+    }
+}
+
+fn default_phrase_cooldown() -> u32 {
+    60
+}
+
+fn default_user_budget_window() -> u32 {
+    60 * 60
+}
+
+/// parses `true` or `false`
+fn parse_bool(key: &str, value: &str) -> Result<bool, String> {
+    value
+        .parse()
+        .map_err(|_| format!("`{key}` must be true or false"))
+}
+
+/// parses a whole number within a range
+fn parse_range<T>(key: &str, value: &str, range: RangeInclusive<T>) -> Result<T, String>
+where
+    T: FromStr + PartialOrd + Display,
+{
+    value
+        .parse::<T>()
+        .ok()
+        .filter(|value| range.contains(value))
+        .ok_or_else(|| {
+            format!(
+                "`{key}` must be a whole number between {} and {}",
+                range.start(),
+                range.end()
+            )
+        })
 }
 impl Data {
-    pub fn new(token: String) -> Self {
+    pub fn new(settings: Settings, storage: Box<dyn Storage>) -> Self {
         Self {
-            client: Client::new(token),
+            client: openai::Client::new(
+                settings.api_token.clone().unwrap_or_default(),
+                settings.api_url.clone(),
+            ),
+            settings,
+            storage,
             guild_meta_map: Default::default(),
+            dirty: Default::default(),
+            changed: Notify::new(),
+            saving: AsyncMutex::new(()),
+            shutting_down: AtomicBool::new(false),
+            in_flight: AtomicUsize::new(0),
+            idle: Notify::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_config_is_valid() {
+        assert!(Config::default().validate().is_ok());
+    }
+
+    #[test]
+    fn set_rejects_values_out_of_range() {
+        let mut config = Config::default();
+        let err = config.set("chance", "250").unwrap_err();
+        assert_eq!(err, "`chance` must be a whole number between 0 and 100");
+        assert!(config.set("cooldown", "0").is_err());
+        assert!(config.set("max_context_len", "5000").is_err());
+        assert!(config.set("user_budget_window", "-1").is_err());
+        assert!(config.set("reply", "yes").is_err());
+        assert!(config.set("cooldown", "soon").is_err());
+        // rejected values don't change the config
+        assert_eq!(config.chance, 25);
+        assert_eq!(config.cooldown, 60);
+
+        config.set("chance", " 100 ").unwrap();
+        assert_eq!(config.chance, 100);
+    }
+
+    #[test]
+    fn set_rejects_unknown_keys() {
+        let err = Config::default().set("volume", "11").unwrap_err();
+        assert_eq!(err, "unknown key `volume`");
+    }
+
+    #[test]
+    fn optional_fields_accept_none() {
+        let mut config = Config::default();
+        config.set("guild_cooldown", "30").unwrap();
+        config.set("user_budget", "5").unwrap();
+        config.set("model_name", "custom").unwrap();
+        assert_eq!(config.guild_cooldown, Some(30));
+        assert_eq!(config.user_budget, Some(5));
+        assert_eq!(config.model_name.as_deref(), Some("custom"));
+
+        config.set("guild_cooldown", "none").unwrap();
+        config.set("user_budget", "").unwrap();
+        config.set("model_name", "none").unwrap();
+        assert_eq!(config.guild_cooldown, None);
+        assert_eq!(config.user_budget, None);
+        assert_eq!(config.model_name, None);
+        assert_eq!(config.get("guild_cooldown").unwrap(), "none");
+        // zero is no budget, not an unlimited one
+        assert!(config.set("user_budget", "0").is_err());
+    }
+
+    #[test]
+    fn selection_round_trips() {
+        for value in ["Argmax", "Softmax(temperature:2.5)", "TopK(3)"] {
+            let mut config = Config::default();
+            config.set("selection", value).unwrap();
+            let formatted = config.get("selection").unwrap();
+
+            let mut parsed = Config::default();
+            parsed.set("selection", &formatted).unwrap();
+            assert_eq!(
+                format!("{:?}", parsed.selection),
+                format!("{:?}", config.selection)
+            );
+        }
+
+        let mut config = Config::default();
+        assert!(config.set("selection", "TopK(0)").is_err());
+        assert!(config
+            .set("selection", "Softmax(temperature: 0.0)")
+            .is_err());
+        assert!(config.set("selection", "Best").is_err());
+        assert!(matches!(config.selection, Selection::Argmax));
+    }
+
+    #[test]
+    fn validate_rejects_invalid_fields() {
+        let config = Config {
+            chance: 250,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config = Config {
+            cooldown: 0,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config = Config {
+            selection: Selection::TopK(0),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn reset_restores_defaults() {
+        let defaults = Config::default();
+        let mut config = Config::default();
+        config.set("chance", "80").unwrap();
+        config.set("user_budget", "3").unwrap();
+
+        config.reset("chance", &defaults).unwrap();
+        config.reset("user_budget", &defaults).unwrap();
+        assert_eq!(config.chance, defaults.chance);
+        assert_eq!(config.user_budget, None);
+        assert!(config.reset("volume", &defaults).is_err());
+    }
+
+    #[test]
+    fn every_key_can_be_formatted() {
+        let config = Config::default();
+        for key in Config::KEYS {
+            assert!(config.get(key).is_some(), "`{key}` can't be formatted");
         }
+        assert!(Config::is_channel_key("chance"));
+        assert!(!Config::is_channel_key("backend"));
     }
 }
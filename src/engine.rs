@@ -0,0 +1,834 @@
+use std::{fmt, time::SystemTime};
+
+use fastrand::Rng;
+use poise::serenity_prelude::{ChannelId, UserId};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    data::{Config, GuildMeta, Phrase, VariantMode},
+    matcher::Hit,
+};
+
+/// the parts of a new message the engine decides on
+pub struct Incoming {
+    pub author: UserId,
+    pub channel: ChannelId,
+}
+
+/// a message of the recent channel history
+pub struct Past<'a> {
+    pub content: &'a str,
+    /// sent by the bot itself
+    pub own: bool,
+}
+
+/// reason for not responding to a message
+#[derive(Debug)]
+pub enum Skip {
+    /// channel is not registered
+    Channel,
+    /// message was sent by the bot
+    Own,
+    /// channel responded recently
+    Cooldown { remaining: u64 },
+    /// any channel of the guild responded recently
+    GuildCooldown { remaining: u64 },
+    /// author used up their trigger budget
+    UserBudget {
+        used: usize,
+        budget: u16,
+        remaining: u64,
+    },
+    /// random chance did not occur
+    Chance { roll: u8, chance: u8 },
+    /// bot is part of the recent history
+    History,
+    /// guild has no phrases to match
+    NoHits,
+    /// best phrase scored below the threshold
+    Threshold {
+        phrase: String,
+        score: f64,
+        minimum: u16,
+    },
+    /// best phrase was used recently
+    PhraseCooldown { phrase: String, remaining: u64 },
+}
+impl fmt::Display for Skip {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Skip::Channel => write!(f, "channel is not registered"),
+            Skip::Own => write!(f, "message is from the bot"),
+            Skip::Cooldown { remaining } => write!(f, "channel on cooldown for {remaining}s"),
+            Skip::GuildCooldown { remaining } => {
+                write!(f, "guild on cooldown for {remaining}s")
+            }
+            Skip::UserBudget {
+                used,
+                budget,
+                remaining,
+            } => write!(
+                f,
+                "author triggered {used} of {budget} responses, next in {remaining}s"
+            ),
+            Skip::Chance { roll, chance } => write!(f, "rolled {roll}, needed at most {chance}"),
+            Skip::History => write!(f, "bot is part of the recent messages"),
+            Skip::NoHits => write!(f, "no phrases to match"),
+            Skip::Threshold {
+                phrase,
+                score,
+                minimum,
+            } => write!(
+                f,
+                "best phrase `{phrase}` scored {score:.2}, below {minimum}"
+            ),
+            Skip::PhraseCooldown { phrase, remaining } => {
+                write!(f, "phrase `{phrase}` on cooldown for {remaining}s")
+            }
+        }
+    }
+}
+
+/// checks the gates that don't need the channel history or scoring
+///
+/// channel allowlist, own messages, channel and guild cooldown, the budget of the author
+/// and random chance, in that order
+pub fn gate(
+    guild_meta: &GuildMeta,
+    message: &Incoming,
+    bot_id: UserId,
+    now: SystemTime,
+    rng: &Rng,
+) -> Result<(), Skip> {
+    if !guild_meta.channels.contains_key(&message.channel) {
+        return Err(Skip::Channel);
+    }
+    let config = guild_meta.channel_config(message.channel);
+    if message.author == bot_id {
+        return Err(Skip::Own);
+    }
+
+    if let Some(remaining) = channel_cooldown(guild_meta, &config, message.channel, now) {
+        return Err(Skip::Cooldown { remaining });
+    }
+    if let Some(remaining) = guild_cooldown(guild_meta, &config, now) {
+        return Err(Skip::GuildCooldown { remaining });
+    }
+    let budget = user_budget(guild_meta, &config, message.author, now);
+    if let (Some(limit), Some(reset)) = (budget.limit, budget.reset) {
+        if budget.used >= limit as usize {
+            return Err(Skip::UserBudget {
+                used: budget.used,
+                budget: limit,
+                remaining: reset,
+            });
+        }
+    }
+
+    let roll = rng.u8(0..=100);
+    if roll > config.chance {
+        return Err(Skip::Chance {
+            roll,
+            chance: config.chance,
+        });
+    }
+
+    Ok(())
+}
+
+/// seconds left on the cooldown of a channel
+pub fn channel_cooldown(
+    guild_meta: &GuildMeta,
+    config: &Config,
+    channel: ChannelId,
+    now: SystemTime,
+) -> Option<u64> {
+    let last_response = guild_meta.last_response.get(&channel)?;
+    remaining(now, *last_response, config.cooldown as u64)
+}
+
+/// seconds left on the guild-wide cooldown, if there is one
+pub fn guild_cooldown(guild_meta: &GuildMeta, config: &Config, now: SystemTime) -> Option<u64> {
+    let last_response = guild_meta.last_guild_response()?;
+    remaining(now, last_response, config.guild_cooldown? as u64)
+}
+
+/// responses a user triggered within the budget window
+pub struct Budget {
+    pub used: usize,
+    /// responses allowed per window, `None` if unlimited
+    pub limit: Option<u16>,
+    /// seconds until the oldest trigger leaves the window
+    pub reset: Option<u64>,
+}
+
+/// counts the responses a user triggered within the budget window
+pub fn user_budget(
+    guild_meta: &GuildMeta,
+    config: &Config,
+    user: UserId,
+    now: SystemTime,
+) -> Budget {
+    let window = config.user_budget_window as u64;
+    let recent = guild_meta
+        .triggers
+        .get(&user)
+        .into_iter()
+        .flatten()
+        .filter_map(|trigger| remaining(now, *trigger, window))
+        .collect::<Vec<_>>();
+
+    Budget {
+        used: recent.len(),
+        limit: config.user_budget,
+        reset: recent.into_iter().min(),
+    }
+}
+
+/// checks that the bot isn't already part of the conversation
+pub fn check_history(history: &[Past]) -> Result<(), Skip> {
+    if history.iter().any(|message| message.own) {
+        Err(Skip::History)
+    } else {
+        Ok(())
+    }
+}
+
+/// merges the history, oldest first, into a query of at most `max_len` bytes of trailing words
+pub fn build_query(history: &[Past], max_len: usize) -> String {
+    let merged = history
+        .iter()
+        .map(|message| message.content)
+        .intersperse(" ")
+        .collect::<String>();
+
+    let mut len = 0;
+    let mut words = merged
+        .split(' ')
+        .rev()
+        .take_while(|word| {
+            len += word.len() + 1;
+            len < max_len
+        })
+        .collect::<Vec<_>>();
+    words.reverse();
+
+    words.join(" ")
+}
+
+/// how the phrase to respond with is chosen among the hits that passed the threshold
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum Selection {
+    /// always the best scoring phrase
+    #[default]
+    Argmax,
+    /// samples phrases weighted by `exp(score / temperature)`, lower temperatures favor the best
+    Softmax { temperature: f64 },
+    /// picks uniformly among the `k` best phrases
+    TopK(usize),
+}
+impl Selection {
+    /// checks the parameters of the strategy
+    pub fn validate(self) -> Result<(), String> {
+        match self {
+            Selection::Softmax { temperature }
+                if !(temperature > 0.0 && temperature.is_finite()) =>
+            {
+                Err("the softmax temperature must be above 0".to_owned())
+            }
+            Selection::TopK(0) => Err("top k needs k of at least 1".to_owned()),
+            _ => Ok(()),
+        }
+    }
+    /// picks an index into `scores`, which must not be empty
+    fn pick(self, scores: &[f64], rng: &Rng) -> usize {
+        let mut order = (0..scores.len()).collect::<Vec<_>>();
+        order.sort_by(|a, b| scores[*b].total_cmp(&scores[*a]));
+        let best = order[0];
+
+        match self {
+            Selection::Argmax => best,
+            Selection::TopK(k) => order[rng.usize(..k.clamp(1, order.len()))],
+            Selection::Softmax { temperature } => {
+                // shifted by the best score so the exponentials can't overflow
+                let weights = scores
+                    .iter()
+                    .map(|score| ((score - scores[best]) / temperature).exp())
+                    .collect::<Vec<_>>();
+                let mut roll = rng.f64() * weights.iter().sum::<f64>();
+                for (index, weight) in weights.iter().enumerate() {
+                    if roll < *weight {
+                        return index;
+                    }
+                    roll -= weight;
+                }
+                best
+            }
+        }
+    }
+}
+
+/// picks the phrase to respond with from ranked hits
+///
+/// hits have to reach the minimum score of the channel, the selection strategy then chooses
+/// among them by their scores times the phrase weights
+///
+/// argmax declines to respond if the chosen phrase is on its cooldown, like it always did,
+/// the sampling strategies only choose among phrases that aren't on their cooldown
+pub fn select(
+    guild_meta: &GuildMeta,
+    channel: ChannelId,
+    hits: Vec<Hit>,
+    now: SystemTime,
+    rng: &Rng,
+) -> Result<Hit, Skip> {
+    let config = guild_meta.channel_config(channel);
+    let minimum = config.minimum_score as f64;
+
+    let best = hits.first().ok_or(Skip::NoHits)?;
+    if best.score < minimum {
+        return Err(Skip::Threshold {
+            phrase: best.phrase.clone(),
+            score: best.score,
+            minimum: config.minimum_score,
+        });
+    }
+
+    let eligible = hits
+        .into_iter()
+        .filter(|hit| hit.score >= minimum)
+        .collect::<Vec<_>>();
+    let weighted = |hits: &[Hit]| {
+        hits.iter()
+            .map(|hit| {
+                let weight = guild_meta
+                    .phrases
+                    .get(&hit.phrase)
+                    .and_then(|phrase| phrase.weight)
+                    .unwrap_or(1.0);
+                hit.score * weight
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // argmax declines when its pick was used recently instead of taking the next best phrase
+    if let Selection::Argmax = config.selection {
+        let mut eligible = eligible;
+        let index = config.selection.pick(&weighted(&eligible), rng);
+        let hit = eligible.swap_remove(index);
+        if let Some(remaining) = phrase_cooldown(guild_meta, &config, &hit.phrase, now) {
+            return Err(Skip::PhraseCooldown {
+                phrase: hit.phrase,
+                remaining,
+            });
+        }
+        return Ok(hit);
+    }
+
+    let (cooling, mut candidates): (Vec<_>, Vec<_>) = eligible
+        .into_iter()
+        .partition(|hit| phrase_cooldown(guild_meta, &config, &hit.phrase, now).is_some());
+
+    // every phrase above the threshold was used recently, report the best one
+    if candidates.is_empty() {
+        let hit = cooling.into_iter().next().ok_or(Skip::NoHits)?;
+        return Err(Skip::PhraseCooldown {
+            remaining: phrase_cooldown(guild_meta, &config, &hit.phrase, now).unwrap_or_default(),
+            phrase: hit.phrase,
+        });
+    }
+
+    let index = config.selection.pick(&weighted(&candidates), rng);
+    Ok(candidates.swap_remove(index))
+}
+
+/// seconds left on the cooldown of a phrase
+pub fn phrase_cooldown(
+    guild_meta: &GuildMeta,
+    config: &Config,
+    phrase: &str,
+    now: SystemTime,
+) -> Option<u64> {
+    let cooldown = guild_meta
+        .phrases
+        .get(phrase)
+        .and_then(|phrase| phrase.cooldown)
+        .unwrap_or(config.phrase_cooldown) as u64;
+    let last_use = guild_meta.cooldown.get(phrase)?;
+    let elapsed = elapsed(now, *last_use);
+    (elapsed <= cooldown).then(|| cooldown - elapsed)
+}
+
+/// the keyword of a phrase found in the query, its first keyword if none was found
+pub fn matched_keyword(entry: &Phrase, query: &str) -> Option<String> {
+    let query = query.to_lowercase();
+    let mut keywords = entry.keywords.iter().collect::<Vec<_>>();
+    keywords.sort();
+
+    keywords
+        .iter()
+        .find(|keyword| query.contains(&keyword.to_lowercase()))
+        .or_else(|| keywords.first())
+        .map(|keyword| (*keyword).clone())
+}
+
+/// picks the text to send for a phrase, the phrase itself or one of its variants
+pub fn pick_response(guild_meta: &mut GuildMeta, phrase: &str, rng: &Rng) -> String {
+    let Some(entry) = guild_meta.phrases.get_mut(phrase) else {
+        return phrase.to_owned()
+    };
+
+    let count = entry.variants.len() + 1;
+    let index = match entry.mode {
+        VariantMode::Random => rng.usize(..count),
+        VariantMode::Rotation => {
+            // variants may have been removed since the rotation started
+            entry.rotation.retain(|index| *index < count);
+            if entry.rotation.is_empty() {
+                entry.rotation = (0..count).collect();
+                rng.shuffle(&mut entry.rotation);
+                // don't repeat the last response across rounds, responses are popped from the back
+                if count > 1 && entry.rotation.last() == entry.last_response.as_ref() {
+                    entry.rotation.swap(0, count - 1);
+                }
+            }
+            entry.rotation.pop().unwrap_or_default()
+        }
+    };
+    entry.last_response = Some(index);
+
+    match index {
+        0 => phrase.to_owned(),
+        index => entry.variants[index - 1].clone(),
+    }
+}
+
+/// whole seconds from `then` to `now`, zero if the clock went backwards
+fn elapsed(now: SystemTime, then: SystemTime) -> u64 {
+    now.duration_since(then).unwrap_or_default().as_secs()
+}
+
+/// seconds left of a cooldown started at `then`, `None` once it ran out
+fn remaining(now: SystemTime, then: SystemTime, cooldown: u64) -> Option<u64> {
+    let elapsed = elapsed(now, then);
+    (elapsed < cooldown).then(|| cooldown - elapsed)
+}
+
+/// starts the channel and phrase cooldowns, counts the use of the phrase
+/// and charges the budget of the author after responding
+pub fn record(guild_meta: &mut GuildMeta, message: &Incoming, phrase: &str, now: SystemTime) {
+    guild_meta.last_response.insert(message.channel, now);
+    guild_meta.cooldown.insert(phrase.to_owned(), now);
+    if let Some(entry) = guild_meta.phrases.get_mut(phrase) {
+        entry.uses += 1;
+    }
+
+    // forget triggers that left the window of every channel
+    let window = guild_meta.longest_budget_window() as u64;
+    guild_meta.triggers.retain(|_, triggers| {
+        triggers.retain(|trigger| elapsed(now, *trigger) < window);
+        !triggers.is_empty()
+    });
+    guild_meta
+        .triggers
+        .entry(message.author)
+        .or_default()
+        .push(now);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use super::*;
+    use crate::data::Channel;
+
+    const CHANNEL: ChannelId = ChannelId(1);
+    const OTHER_CHANNEL: ChannelId = ChannelId(2);
+    const AUTHOR: UserId = UserId(10);
+    const OTHER_USER: UserId = UserId(11);
+    const BOT: UserId = UserId(20);
+
+    fn now() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_000_000)
+    }
+    fn ago(secs: u64) -> SystemTime {
+        now() - Duration::from_secs(secs)
+    }
+    /// a guild with one registered channel that always passes the chance roll
+    fn guild() -> GuildMeta {
+        let mut guild_meta = GuildMeta::default();
+        guild_meta.channels.insert(CHANNEL, Channel::default());
+        guild_meta.config.chance = 100;
+        for phrase in ["a", "b", "c"] {
+            guild_meta
+                .phrases
+                .insert(phrase.to_owned(), Phrase::default());
+        }
+        guild_meta
+    }
+    fn incoming(author: UserId, channel: ChannelId) -> Incoming {
+        Incoming { author, channel }
+    }
+    fn gate_at(guild_meta: &GuildMeta, message: &Incoming) -> Result<(), Skip> {
+        gate(guild_meta, message, BOT, now(), &Rng::with_seed(0))
+    }
+    fn hits(scores: &[(&str, f64)]) -> Vec<Hit> {
+        scores
+            .iter()
+            .map(|(phrase, score)| Hit {
+                phrase: (*phrase).to_owned(),
+                score: *score,
+            })
+            .collect()
+    }
+    fn select_at(guild_meta: &GuildMeta, hits: Vec<Hit>) -> Result<Hit, Skip> {
+        select(guild_meta, CHANNEL, hits, now(), &Rng::with_seed(0))
+    }
+    fn past(content: &str) -> Past {
+        Past {
+            content,
+            own: false,
+        }
+    }
+
+    #[test]
+    fn gate_passes() {
+        assert!(gate_at(&guild(), &incoming(AUTHOR, CHANNEL)).is_ok());
+    }
+
+    #[test]
+    fn gate_skips_unregistered_channel() {
+        let result = gate_at(&guild(), &incoming(AUTHOR, OTHER_CHANNEL));
+        assert!(matches!(result, Err(Skip::Channel)));
+    }
+
+    #[test]
+    fn gate_skips_own_message() {
+        let result = gate_at(&guild(), &incoming(BOT, CHANNEL));
+        assert!(matches!(result, Err(Skip::Own)));
+    }
+
+    #[test]
+    fn gate_skips_channel_on_cooldown() {
+        let mut guild_meta = guild();
+        guild_meta.last_response.insert(CHANNEL, ago(10));
+        let result = gate_at(&guild_meta, &incoming(AUTHOR, CHANNEL));
+        assert!(matches!(result, Err(Skip::Cooldown { remaining: 50 })));
+
+        guild_meta.last_response.insert(CHANNEL, ago(60));
+        assert!(gate_at(&guild_meta, &incoming(AUTHOR, CHANNEL)).is_ok());
+    }
+
+    #[test]
+    fn gate_skips_guild_on_cooldown() {
+        let mut guild_meta = guild();
+        guild_meta.last_response.insert(OTHER_CHANNEL, ago(10));
+        // no guild cooldown by default
+        assert!(gate_at(&guild_meta, &incoming(AUTHOR, CHANNEL)).is_ok());
+
+        guild_meta.config.guild_cooldown = Some(30);
+        let result = gate_at(&guild_meta, &incoming(AUTHOR, CHANNEL));
+        assert!(matches!(result, Err(Skip::GuildCooldown { remaining: 20 })));
+    }
+
+    #[test]
+    fn gate_skips_spent_user_budget() {
+        let mut guild_meta = guild();
+        guild_meta.config.user_budget = Some(2);
+        guild_meta
+            .triggers
+            .insert(AUTHOR, vec![ago(4000), ago(100)]);
+        // the trigger outside the window doesn't count
+        assert!(gate_at(&guild_meta, &incoming(AUTHOR, CHANNEL)).is_ok());
+
+        guild_meta.triggers.insert(AUTHOR, vec![ago(100), ago(10)]);
+        let result = gate_at(&guild_meta, &incoming(AUTHOR, CHANNEL));
+        assert!(matches!(
+            result,
+            Err(Skip::UserBudget {
+                used: 2,
+                budget: 2,
+                remaining: 3500,
+            })
+        ));
+        // the budget is per user
+        assert!(gate_at(&guild_meta, &incoming(OTHER_USER, CHANNEL)).is_ok());
+    }
+
+    #[test]
+    fn gate_rolls_chance() {
+        // a seed whose first roll can fail
+        let seed = (0..)
+            .find(|seed| Rng::with_seed(*seed).u8(0..=100) > 0)
+            .unwrap();
+        let roll = Rng::with_seed(seed).u8(0..=100);
+        let message = incoming(AUTHOR, CHANNEL);
+
+        let mut guild_meta = guild();
+        guild_meta.config.chance = roll;
+        assert!(gate(&guild_meta, &message, BOT, now(), &Rng::with_seed(seed)).is_ok());
+
+        guild_meta.config.chance = roll - 1;
+        let result = gate(&guild_meta, &message, BOT, now(), &Rng::with_seed(seed));
+        assert!(
+            matches!(result, Err(Skip::Chance { roll: rolled, chance }) if rolled == roll && chance == roll - 1)
+        );
+    }
+
+    #[test]
+    fn history_with_own_message_is_skipped() {
+        assert!(check_history(&[past("hello"), past("there")]).is_ok());
+
+        let history = [
+            past("hello"),
+            Past {
+                content: "hi",
+                own: true,
+            },
+        ];
+        assert!(matches!(check_history(&history), Err(Skip::History)));
+    }
+
+    #[test]
+    fn query_keeps_trailing_words() {
+        let history = [past("hello world"), past("foo bar")];
+        assert_eq!(build_query(&history, 100), "hello world foo bar");
+        // "bar" and "foo" take 4 bytes each with their separator
+        assert_eq!(build_query(&history, 9), "foo bar");
+        assert_eq!(build_query(&history, 8), "bar");
+    }
+
+    #[test]
+    fn query_drops_word_longer_than_max_len() {
+        let history = [past("supercalifragilistic")];
+        assert_eq!(build_query(&history, 5), "");
+    }
+
+    #[test]
+    fn select_without_hits() {
+        assert!(matches!(select_at(&guild(), Vec::new()), Err(Skip::NoHits)));
+    }
+
+    #[test]
+    fn select_below_threshold() {
+        let result = select_at(&guild(), hits(&[("a", 10.0)]));
+        assert!(matches!(
+            result,
+            Err(Skip::Threshold { phrase, minimum: 20, .. }) if phrase == "a"
+        ));
+    }
+
+    #[test]
+    fn select_argmax_picks_best_weighted() {
+        let mut guild_meta = guild();
+        let result = select_at(&guild_meta, hits(&[("a", 50.0), ("b", 30.0)])).unwrap();
+        assert_eq!(result.phrase, "a");
+
+        guild_meta.phrases.get_mut("b").unwrap().weight = Some(2.0);
+        let result = select_at(&guild_meta, hits(&[("a", 50.0), ("b", 30.0)])).unwrap();
+        assert_eq!(result.phrase, "b");
+    }
+
+    #[test]
+    fn select_argmax_declines_phrase_on_cooldown() {
+        let mut guild_meta = guild();
+        guild_meta.cooldown.insert("a".to_owned(), ago(10));
+        let result = select_at(&guild_meta, hits(&[("a", 50.0), ("b", 30.0)]));
+        assert!(matches!(
+            result,
+            Err(Skip::PhraseCooldown { phrase, remaining: 50 }) if phrase == "a"
+        ));
+
+        // a phrase cooldown overrides the config
+        guild_meta.phrases.get_mut("a").unwrap().cooldown = Some(5);
+        let result = select_at(&guild_meta, hits(&[("a", 50.0), ("b", 30.0)])).unwrap();
+        assert_eq!(result.phrase, "a");
+    }
+
+    #[test]
+    fn select_sampling_skips_phrases_on_cooldown() {
+        let mut guild_meta = guild();
+        guild_meta.config.selection = Selection::TopK(1);
+        guild_meta.cooldown.insert("a".to_owned(), ago(10));
+        let result = select_at(&guild_meta, hits(&[("a", 50.0), ("b", 30.0)])).unwrap();
+        assert_eq!(result.phrase, "b");
+
+        guild_meta.cooldown.insert("b".to_owned(), ago(10));
+        let result = select_at(&guild_meta, hits(&[("a", 50.0), ("b", 30.0)]));
+        assert!(matches!(
+            result,
+            Err(Skip::PhraseCooldown { phrase, .. }) if phrase == "a"
+        ));
+    }
+
+    #[test]
+    fn select_sampling_ignores_hits_below_threshold() {
+        let mut guild_meta = guild();
+        guild_meta.config.selection = Selection::TopK(3);
+        for seed in 0..20 {
+            let result = select(
+                &guild_meta,
+                CHANNEL,
+                hits(&[("a", 50.0), ("b", 10.0), ("c", 5.0)]),
+                now(),
+                &Rng::with_seed(seed),
+            )
+            .unwrap();
+            assert_eq!(result.phrase, "a");
+        }
+    }
+
+    #[test]
+    fn record_starts_cooldowns_and_charges_budget() {
+        let mut guild_meta = guild();
+        guild_meta.triggers.insert(AUTHOR, vec![ago(10)]);
+        guild_meta.triggers.insert(OTHER_USER, vec![ago(4000)]);
+
+        record(&mut guild_meta, &incoming(AUTHOR, CHANNEL), "a", now());
+
+        assert_eq!(guild_meta.last_response.get(&CHANNEL), Some(&now()));
+        assert_eq!(guild_meta.cooldown.get("a"), Some(&now()));
+        assert_eq!(guild_meta.phrases["a"].uses, 1);
+        assert_eq!(guild_meta.triggers[&AUTHOR], vec![ago(10), now()]);
+        // triggers outside the window are forgotten
+        assert!(!guild_meta.triggers.contains_key(&OTHER_USER));
+    }
+
+    /// a guild whose phrase `a` rotates through variants
+    fn rotating(variants: &[&str]) -> GuildMeta {
+        let mut guild_meta = guild();
+        let entry = guild_meta.phrases.get_mut("a").unwrap();
+        entry.variants = variants
+            .iter()
+            .map(|variant| (*variant).to_owned())
+            .collect();
+        entry.mode = VariantMode::Rotation;
+        guild_meta
+    }
+
+    #[test]
+    fn rotation_sends_every_response_once_per_round() {
+        let mut guild_meta = rotating(&["b", "c"]);
+        let rng = Rng::with_seed(0);
+        for _ in 0..5 {
+            let mut round = (0..3)
+                .map(|_| pick_response(&mut guild_meta, "a", &rng))
+                .collect::<Vec<_>>();
+            round.sort();
+            assert_eq!(round, ["a", "b", "c"]);
+        }
+    }
+
+    #[test]
+    fn rotation_does_not_repeat_across_rounds() {
+        for seed in 0..20 {
+            let mut guild_meta = rotating(&["b", "c"]);
+            let rng = Rng::with_seed(seed);
+            let responses = (0..30)
+                .map(|_| pick_response(&mut guild_meta, "a", &rng))
+                .collect::<Vec<_>>();
+            assert!(
+                responses.windows(2).all(|pair| pair[0] != pair[1]),
+                "seed {seed} repeated a response: {responses:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn rotation_shrinks_after_removing_a_variant() {
+        let mut guild_meta = rotating(&["b"]);
+        let entry = guild_meta.phrases.get_mut("a").unwrap();
+        // the rest of a round started while a third variant existed
+        entry.rotation = vec![2, 1];
+        let rng = Rng::with_seed(0);
+
+        assert_eq!(pick_response(&mut guild_meta, "a", &rng), "b");
+        // the next round starts without repeating `b`
+        assert_eq!(pick_response(&mut guild_meta, "a", &rng), "a");
+        assert_eq!(pick_response(&mut guild_meta, "a", &rng), "b");
+    }
+
+    #[test]
+    fn selection_validates_parameters() {
+        assert!(Selection::Argmax.validate().is_ok());
+        assert!(Selection::TopK(2).validate().is_ok());
+        assert!(Selection::TopK(0).validate().is_err());
+        assert!(Selection::Softmax { temperature: 1.0 }.validate().is_ok());
+        for temperature in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(Selection::Softmax { temperature }.validate().is_err());
+        }
+    }
+
+    /// how often each phrase is selected over many draws
+    fn draws(guild_meta: &GuildMeta, scores: &[(&str, f64)]) -> HashMap<String, usize> {
+        let rng = Rng::with_seed(0);
+        let mut counts = HashMap::new();
+        for _ in 0..1000 {
+            let hit = select(guild_meta, CHANNEL, hits(scores), now(), &rng).unwrap();
+            *counts.entry(hit.phrase).or_default() += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn softmax_favors_higher_weighted_scores() {
+        let mut guild_meta = guild();
+        guild_meta.config.selection = Selection::Softmax { temperature: 10.0 };
+        guild_meta.phrases.get_mut("b").unwrap().weight = Some(3.0);
+
+        // `b` weighs 90 against the 50 of `a`
+        let counts = draws(&guild_meta, &[("a", 50.0), ("b", 30.0)]);
+        assert!(counts["b"] > 900, "{counts:?}");
+        assert!(
+            counts.get("a").copied().unwrap_or_default() > 0,
+            "{counts:?}"
+        );
+    }
+
+    #[test]
+    fn softmax_with_low_temperature_is_argmax() {
+        let mut guild_meta = guild();
+        guild_meta.config.selection = Selection::Softmax { temperature: 0.01 };
+
+        let counts = draws(&guild_meta, &[("a", 50.0), ("b", 45.0), ("c", 40.0)]);
+        assert_eq!(counts.get("a"), Some(&1000), "{counts:?}");
+    }
+
+    #[test]
+    fn record_keeps_triggers_a_channel_still_counts() {
+        let mut guild_meta = guild();
+        guild_meta.config.user_budget = Some(2);
+        let overrides = [("user_budget_window".to_owned(), "7200".to_owned())];
+        guild_meta.channels.insert(
+            CHANNEL,
+            Channel {
+                overrides: overrides.into_iter().collect(),
+            },
+        );
+        guild_meta
+            .channels
+            .insert(OTHER_CHANNEL, Channel::default());
+        guild_meta
+            .triggers
+            .insert(AUTHOR, vec![ago(8000), ago(5000)]);
+
+        // responding in a channel with the guild window of an hour
+        record(
+            &mut guild_meta,
+            &incoming(OTHER_USER, OTHER_CHANNEL),
+            "a",
+            now(),
+        );
+
+        // only the trigger outside every window is forgotten
+        assert_eq!(guild_meta.triggers[&AUTHOR], vec![ago(5000)]);
+        let later = now() + Duration::from_secs(60);
+        let config = guild_meta.channel_config(CHANNEL);
+        let budget = user_budget(&guild_meta, &config, AUTHOR, later);
+        assert_eq!(budget.used, 1);
+    }
+}
@@ -1,17 +1,272 @@
-use crate::{data::Data, Error, BOT_ID};
-use gpt3_rs::Request;
-use log::{debug, info};
-use poise::{BoxFuture, Event, FrameworkContext};
-use std::sync::Arc;
-use tokio::time::Instant;
+use crate::{
+    data::{Data, GuildMeta, ResponseKind},
+    engine::{self, Incoming, Past},
+    matcher::{self, Hit},
+    templates::{self, Trigger},
+    Error, BOT_ID,
+};
+use fastrand::Rng;
+use log::{debug, info, warn};
+use poise::{
+    serenity_prelude::{
+        self as serenity, Cache, ChannelId, GuildId, Message, MessageId, ParseValue, ReactionType,
+        RwLock, StickerId, User, UserId,
+    },
+    BoxFuture, Event, FrameworkContext,
+};
+use std::{future::Future, sync::Arc, time::SystemTime};
+
+/// number of messages the query is built from
+const HISTORY_LEN: u64 = 10;
+
+/// fetches a message and the messages before it, newest first
+pub async fn fetch_history(
+    context: &serenity::Context,
+    message: &Message,
+) -> Result<Vec<Message>, Error> {
+    let mut messages = vec![message.clone()];
+    messages.extend(
+        message
+            .channel_id
+            .messages(&context.http, |m| {
+                m.before(message.id).limit(HISTORY_LEN - 1)
+            })
+            .await?,
+    );
+    Ok(messages)
+}
+
+/// a message of the recent channel history, owned so the history can be fetched lazily
+pub struct Recent {
+    pub content: String,
+    /// sent by the bot itself
+    pub own: bool,
+}
+impl Recent {
+    /// the message as the engine sees it
+    pub fn as_past(&self) -> Past<'_> {
+        Past {
+            content: &self.content,
+            own: self.own,
+        }
+    }
+}
+
+/// the fetched messages, oldest first
+pub fn to_recent(context: &serenity::Context, messages: &[Message]) -> Vec<Recent> {
+    messages
+        .iter()
+        .rev()
+        .map(|message| Recent {
+            content: message.content.clone(),
+            own: message.is_own(&context.cache),
+        })
+        .collect()
+}
+
+/// the message a response may be sent for
+pub struct Origin<'a> {
+    pub author: &'a User,
+    pub guild: GuildId,
+    pub channel: ChannelId,
+    pub message: MessageId,
+}
+impl Origin<'_> {
+    fn incoming(&self) -> Incoming {
+        Incoming {
+            author: self.author.id,
+            channel: self.channel,
+        }
+    }
+}
+
+/// the response picked for a message
+pub struct Decision {
+    pub hit: Hit,
+    /// the query the phrase was matched against
+    pub query: String,
+    /// the phrase or one of its variants, before rendering
+    pub response: String,
+}
+
+/// runs a message through the gates, matches its history and picks the response
+///
+/// the history is only awaited once the message passed the gates, the response is recorded
+/// as sent and the guild marked as dirty, the rng is owned so the future stays `Send`
+pub async fn decide(
+    data: &Data,
+    guild_meta: &mut GuildMeta,
+    origin: &Origin<'_>,
+    bot_id: UserId,
+    now: SystemTime,
+    rng: Rng,
+    history: impl Future<Output = Result<Vec<Recent>, Error>>,
+) -> Result<Option<Decision>, Error> {
+    let incoming = origin.incoming();
+    if engine::gate(guild_meta, &incoming, bot_id, now, &rng).is_err() {
+        return Ok(None);
+    }
+    debug!("message chance occured");
+
+    let history = history.await?;
+    let history = history.iter().map(Recent::as_past).collect::<Vec<_>>();
+    if let Err(skip) = engine::check_history(&history) {
+        debug!("skipped: {}", skip);
+        return Ok(None);
+    }
+
+    let config = guild_meta.channel_config(incoming.channel);
+    let query = engine::build_query(&history, config.max_context_len);
+    debug!("query:\n{}", query);
+
+    let hits = matcher::score(data, guild_meta, &query).await?;
+    let hit = match engine::select(guild_meta, incoming.channel, hits, now, &rng) {
+        Ok(hit) => hit,
+        Err(skip) => {
+            debug!("skipped: {}", skip);
+            return Ok(None);
+        }
+    };
+
+    engine::record(guild_meta, &incoming, &hit.phrase, now);
+    let response = engine::pick_response(guild_meta, &hit.phrase, &rng);
+    // keep cooldowns and rotations across restarts
+    data.mark_dirty(origin.guild);
+    info!("found catchphrase: {}", hit.phrase);
+
+    Ok(Some(Decision {
+        hit,
+        query,
+        response,
+    }))
+}
+
+/// what the bot sends for a response, executed by `send`
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outgoing {
+    /// a message in the channel of the triggering message
+    Message {
+        content: String,
+        /// the triggering message, if the response replies to it
+        reply_to: Option<MessageId>,
+        /// whether the reply pings the author of the triggering message,
+        /// mentions in the content can only ever ping users
+        ping: bool,
+    },
+    /// reactions to the triggering message
+    React(Vec<ReactionType>),
+    /// a sticker in the channel of the triggering message
+    Sticker(StickerId),
+}
+
+/// renders a decided response and describes how it is sent, the way its phrase asks for
+pub fn compose(
+    guild_meta: &GuildMeta,
+    decision: &Decision,
+    origin: &Origin<'_>,
+    cache: &Cache,
+    rng: &Rng,
+) -> Result<Outgoing, Error> {
+    let config = guild_meta.channel_config(origin.channel);
+    let entry = guild_meta.phrases.get(&decision.hit.phrase);
+    let trigger = Trigger {
+        author: origin.author,
+        channel: origin.channel,
+        guild: origin.guild,
+        keyword: entry.and_then(|entry| engine::matched_keyword(entry, &decision.query)),
+        count: entry.map_or(0, |entry| entry.uses),
+    };
+
+    match entry.map(|entry| entry.kind.clone()).unwrap_or_default() {
+        kind @ (ResponseKind::Message | ResponseKind::Reply) => {
+            // phrases stored before templates existed may not parse, those are sent as is
+            let content = match templates::render(&decision.response, &trigger, cache, rng) {
+                Ok(rendered) => rendered,
+                Err(err) => {
+                    warn!("sending phrase without template: {}", err);
+                    decision.response.clone()
+                }
+            };
+            let reply = kind == ResponseKind::Reply || config.reply;
+            let ping = entry
+                .and_then(|entry| entry.ping)
+                .unwrap_or(config.reply_ping);
+
+            Ok(Outgoing::Message {
+                content,
+                reply_to: reply.then_some(origin.message),
+                ping: reply && ping,
+            })
+        }
+        ResponseKind::React(emoji) => emoji
+            .iter()
+            .map(|emoji| {
+                emoji
+                    .parse::<ReactionType>()
+                    .map_err(|_| Error::from(format!("invalid emoji `{emoji}`")))
+            })
+            .collect::<Result<_, Error>>()
+            .map(Outgoing::React),
+        ResponseKind::Sticker(sticker) => Ok(Outgoing::Sticker(sticker)),
+    }
+}
+
+/// sends a composed response
+pub async fn send(
+    context: &serenity::Context,
+    origin: &Origin<'_>,
+    outgoing: Outgoing,
+) -> Result<(), Error> {
+    match outgoing {
+        Outgoing::Message {
+            content,
+            reply_to,
+            ping,
+        } => {
+            origin
+                .channel
+                .send_message(&context.http, |m| {
+                    m.content(content);
+                    if let Some(message) = reply_to {
+                        m.reference_message((origin.channel, message));
+                    }
+                    // phrases may only ping users, never everyone or roles
+                    m.allowed_mentions(|mentions| {
+                        mentions.parse(ParseValue::Users).replied_user(ping)
+                    })
+                })
+                .await?;
+        }
+        Outgoing::React(reactions) => {
+            for reaction in reactions {
+                origin
+                    .channel
+                    .create_reaction(&context.http, origin.message, reaction)
+                    .await?;
+            }
+        }
+        Outgoing::Sticker(sticker) => {
+            origin
+                .channel
+                .send_message(&context.http, |m| m.add_sticker_id(sticker))
+                .await?;
+        }
+    }
+    Ok(())
+}
 
 pub fn listener<'a>(
-    context: &'a poise::serenity_prelude::Context,
+    context: &'a serenity::Context,
     event: &'a Event<'a>,
     _framework: FrameworkContext<'a, Arc<Data>, Error>,
     data: &'a Arc<Data>,
 ) -> BoxFuture<'a, Result<(), Error>> {
     Box::pin(async move {
+        // ignore events while shutting down
+        let Some(_event_guard) = data.begin_event() else {
+            return Ok(())
+        };
+
         match event {
             Event::Message { new_message } => {
                 // return if not guild message
@@ -26,149 +281,404 @@ pub fn listener<'a>(
                 // guild metadata guard
                 let mut guild_meta = guild_meta_lock.write().await;
 
-                let config = &guild_meta.config;
-                let chance = config.chance;
-                let context_len = config.max_context_len;
-                let minimum_score = config.minimum_score;
-                let cooldown = config.cooldown;
-                let last_response = guild_meta.last_response;
-                let phrases = &guild_meta.phrases;
-                let channels = &guild_meta.channels;
-
-                if !channels.contains(&new_message.channel_id) {
-                    return Ok(());
-                }
-                if new_message.author.id == *BOT_ID.get().unwrap() {
-                    return Ok(());
-                }
-
-                // return if on cooldown
-                match last_response {
-                    Some(last_response) if last_response.elapsed().as_secs() < cooldown as u64 => {
-                        return Ok(())
-                    }
-                    _ => {}
-                }
-
-                if fastrand::u8(0..=100) <= chance {
-                    debug!("message chance occured");
-                    
-                    // fetches recent messages
-                    let messages = new_message
-                        .channel_id
-                        .messages(&context.http, |m| m.limit(10))
-                        .await?;
-
-                    // return if any of the messages are from itself
-                    if messages.iter().any(|message| message.is_own(&context.cache)) {
-                        return Ok(())
-                    }
+                let origin = Origin {
+                    author: &new_message.author,
+                    guild: guild_id,
+                    channel: new_message.channel_id,
+                    message: new_message.id,
+                };
+                let bot_id = *BOT_ID.get().unwrap();
+                let history = async {
+                    let messages = fetch_history(context, new_message).await?;
+                    Ok::<_, Error>(to_recent(context, &messages))
+                };
 
-                    // collect phrases for request
-                    let (phrases_content, keyword_content): (Vec<_>, Vec<_>) = phrases
-                        .iter()
-                        .map(|(phrase, keywords)| {
-                            (
-                                phrase.clone(),
-                                keywords
-                                    .iter()
-                                    .map(|keyword| &**keyword)
-                                    .intersperse(", ")
-                                    .collect::<String>(),
-                            )
-                        })
-                        .unzip();
-
-                    let documents = phrases_content
-                        .iter()
-                        .zip(keyword_content.iter())
-                        .map(|(phrase, keywords)| {
-                            if keywords.is_empty() {
-                                phrase
-                            } else {
-                                keywords
-                            }
-                        })
-                        .cloned()
-                        .collect::<Vec<_>>();
-
-                    let client = &data.client;
-
-                    // merges recent messages into one
-                    let merged = messages
-                        .iter()
-                        .rev()
-                        .map(|messages| &*messages.content)
-                        .intersperse(" ")
-                        .collect::<String>();
-
-                    // finds start index of message within bounds of max context length
-                    let mut acc = 0;
-                    let start = merged
-                        .split(' ')
-                        .rev()
-                        .take_while(|word| {
-                            acc += word.len() + 1;
-                            acc < context_len
-                        })
-                        .fold(0, |start, word| start + word.len() + 1);
-                    let (_, message_text) = merged.split_at(merged.len() - (start - 1));
-
-                    debug!("query:\n{}", message_text);
-                    debug!("documents:\n{:#?}", documents);
-
-                    // build search request
-                    let response = gpt3_rs::api::searches::Builder::default()
-                        .model(gpt3_rs::Model::Babbage)
-                        .documents(documents)
-                        .query(message_text)
-                        .build()?
-                        .request(client)
-                        .await?;
-
-                    debug!("response:\n{:#?}", response);
-
-                    // gets highest score and check if it's above the threshold
-                    let index = response
-                        .data
-                        .into_iter()
-                        .reduce(|acc, data| if acc.score < data.score { data } else { acc })
-                        .filter(|data| data.score >= minimum_score as f64)
-                        .map(|data| data.document);
-
-                    if let Some(catchphrase) = index.and_then(|i| phrases_content.get(i)).cloned() {
-                        let phrase_cooldown = guild_meta.cooldown.get(&catchphrase);
-
-                        // if phrase has cooldown
-                        if phrase_cooldown.is_some_and(|last_phrase| {
-                            last_phrase.elapsed().as_secs() > cooldown as u64
-                        }) || phrase_cooldown.is_none()
-                        {
-                            // reset cooldowns
-                            guild_meta.last_response = Some(Instant::now());
-                            guild_meta
-                                .cooldown
-                                .insert(catchphrase.clone(), Instant::now());
-
-                            info!("found catchphrase: {}", catchphrase);
-
-                            // send phrase
-                            new_message
-                                .channel_id
-                                .say(&context.http, catchphrase)
-                                .await?;
-                        }
-                    }
-                }
+                let Some(decision) = decide(
+                    data,
+                    &mut guild_meta,
+                    &origin,
+                    bot_id,
+                    SystemTime::now(),
+                    Rng::new(),
+                    history,
+                )
+                .await?
+                else {
+                    return Ok(())
+                };
+                let outgoing =
+                    compose(&guild_meta, &decision, &origin, &context.cache, &Rng::new())?;
+                send(context, &origin, outgoing).await?;
             }
             // adds newly joined guilds to the guild map
             Event::GuildCreate { guild, .. } => {
                 let guild_id = guild.id;
                 let mut guild_meta_map = data.guild_meta_map.write().await;
-                guild_meta_map.entry(guild_id).or_default();
+                guild_meta_map
+                    .entry(guild_id)
+                    .or_insert_with(|| Arc::new(RwLock::new(data.new_guild())));
             }
             _ => {}
         }
         Ok(())
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::Cell,
+        collections::HashSet,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use poise::serenity_prelude::EmojiId;
+
+    use super::*;
+    use crate::{
+        data::{Channel, Phrase},
+        matcher::Backend,
+        testing::{self, StubApi},
+    };
+
+    const GUILD: GuildId = GuildId(1);
+    const CHANNEL: ChannelId = ChannelId(2);
+    const OTHER_CHANNEL: ChannelId = ChannelId(3);
+    const MESSAGE: MessageId = MessageId(4);
+    const AUTHOR: UserId = UserId(10);
+    const BOT: UserId = UserId(20);
+    const VOCABULARY: &[&str] = &["hello there", "high ground"];
+
+    fn now() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_000_000)
+    }
+    fn author() -> User {
+        User {
+            id: AUTHOR,
+            name: "kenobi".to_owned(),
+            ..Default::default()
+        }
+    }
+    fn origin(author: &User) -> Origin<'_> {
+        Origin {
+            author,
+            guild: GUILD,
+            channel: CHANNEL,
+            message: MESSAGE,
+        }
+    }
+    /// a registered channel with two phrases that always passes the chance roll
+    fn guild(backend: Backend) -> GuildMeta {
+        let mut guild_meta = GuildMeta::default();
+        guild_meta.channels.insert(CHANNEL, Channel::default());
+        guild_meta.config.chance = 100;
+        guild_meta.config.backend = backend;
+        for (phrase, keyword) in [
+            ("general kenobi", "hello there"),
+            ("it's over anakin", "high ground"),
+        ] {
+            let entry = Phrase {
+                keywords: HashSet::from([keyword.to_owned()]),
+                ..Default::default()
+            };
+            guild_meta.phrases.insert(phrase.to_owned(), entry);
+        }
+        guild_meta
+    }
+    /// recent messages by users, noting when they are fetched
+    fn history<'a>(
+        contents: &[&str],
+        fetched: &'a Cell<bool>,
+    ) -> impl Future<Output = Result<Vec<Recent>, Error>> + 'a {
+        let recent = contents
+            .iter()
+            .map(|content| Recent {
+                content: (*content).to_owned(),
+                own: false,
+            })
+            .collect::<Vec<_>>();
+        async move {
+            fetched.set(true);
+            Ok::<_, Error>(recent)
+        }
+    }
+    /// runs a message through the listener steps up to the composed response
+    async fn respond(
+        data: &Data,
+        guild_meta: &mut GuildMeta,
+        contents: &[&str],
+        now: SystemTime,
+    ) -> Option<Outgoing> {
+        let author = author();
+        let origin = origin(&author);
+        let fetched = Cell::new(false);
+        let decision = decide(
+            data,
+            guild_meta,
+            &origin,
+            BOT,
+            now,
+            Rng::with_seed(0),
+            history(contents, &fetched),
+        )
+        .await
+        .unwrap()?;
+        assert!(fetched.get());
+        let outgoing = compose(guild_meta, &decision, &origin, &Cache::new(), &Rng::new());
+        Some(outgoing.unwrap())
+    }
+    fn message(content: &str) -> Outgoing {
+        Outgoing::Message {
+            content: content.to_owned(),
+            reply_to: None,
+            ping: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_the_rendered_phrase() {
+        let data = testing::data();
+        let mut guild_meta = guild(Backend::Bm25);
+        let entry = guild_meta.phrases.remove("general kenobi").unwrap();
+        guild_meta
+            .phrases
+            .insert("{keyword}, {author} #{count}".to_owned(), entry);
+
+        let outgoing = respond(&data, &mut guild_meta, &["well", "hello there"], now()).await;
+        assert_eq!(outgoing, Some(message("hello there, kenobi #1")));
+
+        // the response was recorded and the guild will be saved
+        assert_eq!(data.take_dirty(), [GUILD]);
+        assert!(guild_meta
+            .cooldown
+            .contains_key("{keyword}, {author} #{count}"));
+        assert_eq!(guild_meta.last_response.get(&CHANNEL), Some(&now()));
+    }
+
+    #[tokio::test]
+    async fn sends_invalid_templates_as_is() {
+        let data = testing::data();
+        let mut guild_meta = guild(Backend::Bm25);
+        guild_meta
+            .phrases
+            .insert("hello there {".to_owned(), Phrase::default());
+        guild_meta.phrases.remove("general kenobi");
+
+        let outgoing = respond(&data, &mut guild_meta, &["hello there"], now()).await;
+        assert_eq!(outgoing, Some(message("hello there {")));
+    }
+
+    #[tokio::test]
+    async fn replies_like_the_config_and_phrase_ask() {
+        let data = testing::data();
+        let mut guild_meta = guild(Backend::Bm25);
+        guild_meta.config.reply = true;
+        guild_meta.config.reply_ping = true;
+
+        let outgoing = respond(&data, &mut guild_meta, &["hello there"], now()).await;
+        let expected = Outgoing::Message {
+            content: "general kenobi".to_owned(),
+            reply_to: Some(MESSAGE),
+            ping: true,
+        };
+        assert_eq!(outgoing, Some(expected));
+
+        // the phrase replies on its own and doesn't ping
+        guild_meta.config.reply = false;
+        let entry = guild_meta.phrases.get_mut("it's over anakin").unwrap();
+        entry.kind = ResponseKind::Reply;
+        entry.ping = Some(false);
+        let later = now() + Duration::from_secs(60 * 60);
+        let outgoing = respond(&data, &mut guild_meta, &["the high ground"], later).await;
+        let expected = Outgoing::Message {
+            content: "it's over anakin".to_owned(),
+            reply_to: Some(MESSAGE),
+            ping: false,
+        };
+        assert_eq!(outgoing, Some(expected));
+    }
+
+    #[tokio::test]
+    async fn reacts_with_emoji() {
+        let data = testing::data();
+        let mut guild_meta = guild(Backend::Bm25);
+        guild_meta.phrases.get_mut("general kenobi").unwrap().kind =
+            ResponseKind::React(vec!["👋".to_owned(), "<:kenobi:123>".to_owned()]);
+
+        let outgoing = respond(&data, &mut guild_meta, &["hello there"], now()).await;
+        let expected = Outgoing::React(vec![
+            ReactionType::Unicode("👋".to_owned()),
+            ReactionType::Custom {
+                animated: false,
+                id: EmojiId(123),
+                name: Some("kenobi".to_owned()),
+            },
+        ]);
+        assert_eq!(outgoing, Some(expected));
+    }
+
+    #[tokio::test]
+    async fn fails_on_invalid_emoji() {
+        let data = testing::data();
+        let mut guild_meta = guild(Backend::Bm25);
+        guild_meta.phrases.get_mut("general kenobi").unwrap().kind =
+            ResponseKind::React(vec!["<:kenobi>".to_owned()]);
+        let author = author();
+        let origin = origin(&author);
+        let fetched = Cell::new(false);
+
+        let decision = decide(
+            &data,
+            &mut guild_meta,
+            &origin,
+            BOT,
+            now(),
+            Rng::with_seed(0),
+            history(&["hello there"], &fetched),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let result = compose(&guild_meta, &decision, &origin, &Cache::new(), &Rng::new());
+        assert_eq!(result.unwrap_err().to_string(), "invalid emoji `<:kenobi>`");
+    }
+
+    #[tokio::test]
+    async fn sends_stickers() {
+        let data = testing::data();
+        let mut guild_meta = guild(Backend::Bm25);
+        guild_meta.phrases.get_mut("general kenobi").unwrap().kind =
+            ResponseKind::Sticker(StickerId(5));
+
+        let outgoing = respond(&data, &mut guild_meta, &["hello there"], now()).await;
+        assert_eq!(outgoing, Some(Outgoing::Sticker(StickerId(5))));
+    }
+
+    #[tokio::test]
+    async fn gates_before_fetching_the_history() {
+        let data = testing::data();
+        let mut guild_meta = guild(Backend::Bm25);
+        let author = author();
+        let origin = Origin {
+            channel: OTHER_CHANNEL,
+            ..origin(&author)
+        };
+        let fetched = Cell::new(false);
+
+        let decision = decide(
+            &data,
+            &mut guild_meta,
+            &origin,
+            BOT,
+            now(),
+            Rng::with_seed(0),
+            history(&["hello there"], &fetched),
+        )
+        .await
+        .unwrap();
+        assert!(decision.is_none());
+        assert!(!fetched.get());
+        assert!(data.take_dirty().is_empty());
+    }
+
+    #[tokio::test]
+    async fn skips_conversations_with_the_bot() {
+        let data = testing::data();
+        let mut guild_meta = guild(Backend::Bm25);
+        let author = author();
+        let history = async {
+            Ok::<_, Error>(vec![
+                Recent {
+                    content: "general kenobi".to_owned(),
+                    own: true,
+                },
+                Recent {
+                    content: "hello there".to_owned(),
+                    own: false,
+                },
+            ])
+        };
+
+        let decision = decide(
+            &data,
+            &mut guild_meta,
+            &origin(&author),
+            BOT,
+            now(),
+            Rng::with_seed(0),
+            history,
+        )
+        .await
+        .unwrap();
+        assert!(decision.is_none());
+        assert!(guild_meta.cooldown.is_empty());
+        assert!(data.take_dirty().is_empty());
+    }
+
+    #[tokio::test]
+    async fn skips_unrelated_messages() {
+        let data = testing::data();
+        let mut guild_meta = guild(Backend::Bm25);
+
+        let outgoing = respond(&data, &mut guild_meta, &["nice weather"], now()).await;
+        assert!(outgoing.is_none());
+        assert!(guild_meta.last_response.is_empty());
+        assert!(data.take_dirty().is_empty());
+    }
+
+    #[tokio::test]
+    async fn responds_through_search() {
+        let api = StubApi::start(VOCABULARY).await;
+        let data = api.data();
+        let mut guild_meta = guild(Backend::Search);
+
+        let outgoing = respond(
+            &data,
+            &mut guild_meta,
+            &["i have been waiting", "well hello there"],
+            now(),
+        )
+        .await;
+        assert_eq!(outgoing, Some(message("general kenobi")));
+
+        let requests = api.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path, "/engines/babbage/search");
+        assert_eq!(
+            requests[0].body["query"],
+            "i have been waiting well hello there"
+        );
+    }
+
+    #[tokio::test]
+    async fn responds_through_embeddings() {
+        let api = StubApi::start(VOCABULARY).await;
+        let data = api.data();
+        let mut guild_meta = guild(Backend::Embeddings);
+
+        let outgoing = respond(
+            &data,
+            &mut guild_meta,
+            &["you underestimate my power", "i have the high ground"],
+            now(),
+        )
+        .await;
+        assert_eq!(outgoing, Some(message("it's over anakin")));
+
+        // the phrases are embedded once, then the query
+        let requests = api.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests.iter().all(|request| request.path == "/embeddings"));
+        assert_eq!(requests[0].body["input"].as_array().unwrap().len(), 2);
+        assert_eq!(
+            requests[1].body["input"][0],
+            "you underestimate my power i have the high ground"
+        );
+
+        // after the cooldowns only the query is embedded
+        let later = now() + Duration::from_secs(60 * 60);
+        let outgoing = respond(&data, &mut guild_meta, &["hello there"], later).await;
+        assert_eq!(outgoing, Some(message("general kenobi")));
+        assert_eq!(api.requests().len(), 1);
+    }
+}
@@ -5,65 +5,130 @@
 
 mod commands;
 mod data;
+mod engine;
 mod listener;
+mod matcher;
+mod openai;
+mod permissions;
+mod schema;
+mod settings;
+mod shutdown;
+mod storage;
+mod templates;
+#[cfg(test)]
+mod testing;
 
 use data::Data;
-use log::{debug, info, warn};
+use log::{debug, error, info, warn};
 use poise::{
-    serenity_prelude::{self as serenity, Color, Colour, GuildId, RwLock, UserId},
+    serenity_prelude::{self as serenity, RwLock, UserId},
     PrefixFrameworkOptions,
 };
 use std::sync::Arc;
-use tokio::sync::OnceCell;
+use tokio::sync::{oneshot, OnceCell};
 
-use crate::data::GuildMeta;
+use crate::{
+    settings::{Settings, StorageKind},
+    storage::{RonFiles, Sqlite, Storage},
+};
 
 type Error = Box<dyn std::error::Error + Send + Sync>;
 type Context<'a> = poise::Context<'a, Arc<Data>, Error>;
 
 static BOT_ID: OnceCell<UserId> = OnceCell::const_new();
-const EMBED_COLOR: Color = Colour(0x2F3136);
-const DATA_PATH: &str = "guilds";
 
 #[tokio::main]
 async fn main() {
-    let bot_token = std::env::var("GPT_BOT_TOKEN").unwrap();
-    let gpt_token = std::env::var("GPT_API_TOKEN").unwrap();
+    let settings = match Settings::load() {
+        Ok(settings) => settings,
+        Err(err) => {
+            eprintln!("error loading settings: {}", err);
+            std::process::exit(1);
+        }
+    };
 
     // init logger
     env_logger::builder()
-        .filter(Some("catchphrase"), log::LevelFilter::Debug)
+        .parse_filters(&settings.log_filter)
         .init();
 
-    // start framework
-    poise::Framework::build()
+    if let Err(err) = run(settings).await {
+        error!("{}", err);
+        std::process::exit(1);
+    }
+    info!("shut down");
+}
+
+/// runs the bot until it is shut down
+async fn run(settings: Settings) -> Result<(), Error> {
+    // only the remote backends need the api token
+    if settings.api_token.is_none() {
+        warn!("no api token set, only local backends will work");
+    }
+    if settings.owners.is_empty() {
+        warn!("no owners set, owner only commands can't be used");
+    }
+
+    let storage: Box<dyn Storage> = match settings.storage {
+        StorageKind::Ron => Box::new(RonFiles::new(&settings.data_path)),
+        StorageKind::Sqlite => Box::new(
+            Sqlite::open(format!("{}.sqlite", settings.data_path))
+                .map_err(|err| format!("error opening database: {}", err))?,
+        ),
+    };
+
+    let bot_token = settings.bot_token.clone().unwrap_or_default();
+    let prefix = settings.prefix.clone();
+    let owners = settings.owners.clone();
+
+    let data = Arc::new(Data::new(settings, storage));
+    let setup_data = data.clone();
+
+    // save guilds shortly after they change
+    let (stop_autosave, autosave_stopped) = oneshot::channel();
+    let autosave = tokio::spawn(storage::autosave(data.clone(), autosave_stopped));
+
+    // build framework
+    let framework = poise::Framework::build()
         .token(bot_token)
         .options(poise::FrameworkOptions {
             commands: vec![
                 commands::register(),
                 commands::add_catchphrase(),
                 commands::remove_catchphrase(),
+                commands::set_phrase_cooldown(),
+                commands::add_variant(),
+                commands::remove_variant(),
+                commands::set_variant_mode(),
+                commands::set_response_kind(),
+                commands::set_reply_ping(),
+                commands::set_phrase_weight(),
+                commands::test_match(),
+                commands::test_match_message(),
+                commands::preview_phrase(),
+                commands::status(),
                 commands::list_catchphrases(),
                 commands::show_config(),
                 commands::load_config(),
+                commands::config(),
                 commands::load_phrases(),
                 commands::dump_configs(),
                 commands::add_channel(),
                 commands::remove_channel(),
+                commands::reset_guild(),
+                commands::show_access(),
+                commands::grant_role(),
+                commands::revoke_role(),
+                commands::set_access_permissions(),
             ],
             prefix_options: PrefixFrameworkOptions {
-                prefix: Some("!".into()),
+                prefix: Some(prefix),
                 ..Default::default()
             },
-            owners: {
-                [
-                    UserId(375636762094993419),
-                    UserId(821483662297923664),
-                    UserId(842700734583930881),
-                ]
-                .into()
-            },
+            owners,
             listener: listener::listener,
+            // reject commands while shutting down
+            command_check: Some(|ctx| Box::pin(async move { Ok(!ctx.data().is_shutting_down()) })),
             ..Default::default()
         })
         .intents(
@@ -76,43 +141,27 @@ async fn main() {
                 BOT_ID
                     .get_or_init(async || _ctx.cache.current_user_id())
                     .await;
-                let data = Arc::new(Data::new(gpt_token));
+                let data = setup_data;
 
                 let mut guild_meta_map = data.guild_meta_map.write().await;
 
                 // init joined guilds with default values
                 for guild in _ready.guilds.iter() {
-                    guild_meta_map.entry(guild.id).or_default();
+                    guild_meta_map
+                        .entry(guild.id)
+                        .or_insert_with(|| Arc::new(RwLock::new(data.new_guild())));
                     debug!("initialized: {}", guild.id)
                 }
 
-                // walk dir and add config files to data
-                if let Ok(mut guild_files) = tokio::fs::read_dir(DATA_PATH).await {
-                    while let Ok(Some(guild_file)) = guild_files.next_entry().await {
-                        // cursed "try" block ( || {} )()
-                        match (async || -> Result<_, Error> {
-                            let file_name = guild_file.file_name();
-                            let file_name = file_name.to_string_lossy();
-
-                            // get id from file name
-                            let id = file_name[..file_name.len() - 4].parse::<u64>()?;
-                            // file content
-                            let bytes = tokio::fs::read(guild_file.path()).await?;
-                            // deserialzed metadata
-                            let guild_meta = ron::de::from_bytes::<GuildMeta>(&bytes)?;
-
-                            Ok((id, guild_meta))
-                        })()
-                        .await
-                        {
-                            Ok((id, guild_meta)) => {
-                                debug!("loaded guild: {}", id);
-                                guild_meta_map
-                                    .insert(GuildId(id), Arc::new(RwLock::new(guild_meta)));
-                            }
-                            Err(err) => warn!("error loading guild: {}", err),
+                // add stored guilds to data
+                match data.storage.load_all().await {
+                    Ok(guilds) => {
+                        for (guild_id, guild_meta) in guilds {
+                            debug!("loaded guild: {}", guild_id);
+                            guild_meta_map.insert(guild_id, Arc::new(RwLock::new(guild_meta)));
                         }
                     }
+                    Err(err) => warn!("error loading guilds: {}", err),
                 }
 
                 drop(guild_meta_map);
@@ -120,7 +169,36 @@ async fn main() {
                 Ok(data)
             })
         })
-        .run()
+        .build()
         .await
-        .unwrap();
+        .map_err(|err| format!("error starting bot: {}", err))?;
+
+    // shut down cleanly on SIGINT and SIGTERM
+    let shard_manager = framework.shard_manager();
+    let shutdown_data = data.clone();
+    tokio::spawn(async move {
+        shutdown::signal().await;
+        shutdown::shutdown(&shutdown_data, shard_manager).await;
+    });
+
+    // start framework
+    let result = framework.start().await;
+
+    // let autosave finish a running save, then give events that outlived the shutdown
+    // another chance to finish before the final flush of unsaved changes
+    let _ = stop_autosave.send(());
+    if let Err(err) = autosave.await {
+        warn!("autosave task failed: {}", err);
+    }
+    shutdown::drain(&data).await;
+    let errors = storage::save_dirty(&data).await;
+    if !data.is_idle() {
+        warn!("events are still running, their changes are not saved");
+    }
+
+    result.map_err(|err| format!("error running bot: {}", err))?;
+    if errors > 0 {
+        return Err(format!("failed to save {} guilds", errors).into());
+    }
+    Ok(())
 }
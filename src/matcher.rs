@@ -0,0 +1,176 @@
+mod bm25;
+mod embeddings;
+mod search;
+
+use std::collections::HashSet;
+
+use gpt3_rs::Model;
+use poise::BoxFuture;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    data::{Config, Data, GuildMeta},
+    Error,
+};
+
+pub use bm25::Bm25;
+pub use embeddings::{EmbeddingIndex, Embeddings};
+pub use search::Search;
+
+/// a phrase scored against a query
+#[derive(Debug, Clone)]
+pub struct Hit {
+    pub phrase: String,
+    pub score: f64,
+}
+
+/// scores a query against the phrases of a guild
+pub trait Matcher: Sync {
+    /// prepares the phrases of a guild for ranking, called whenever phrases may have changed
+    fn index<'a>(
+        &'a self,
+        _data: &'a Data,
+        _guild_meta: &'a mut GuildMeta,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async { Ok(()) })
+    }
+    /// returns every phrase of the guild with its score, highest score first
+    fn rank<'a>(
+        &'a self,
+        data: &'a Data,
+        guild_meta: &'a GuildMeta,
+        query: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<Hit>, Error>>;
+    /// checks if the backend can match with the model of a config
+    fn supports(&self, config: &Config) -> bool;
+    /// describes what a single match costs with the model of a config
+    fn cost(&self, config: &Config) -> String;
+}
+
+/// indexes and ranks the phrases of a guild with the backend of the guild
+pub async fn score(
+    data: &Data,
+    guild_meta: &mut GuildMeta,
+    query: &str,
+) -> Result<Vec<Hit>, Error> {
+    let backend = guild_meta.config.backend;
+    let matcher = backend.matcher();
+    if !matcher.supports(&guild_meta.config) {
+        return Err(format!("model not supported by backend {:?}", backend).into());
+    }
+    matcher.index(data, guild_meta).await?;
+    matcher.rank(data, guild_meta, query).await
+}
+
+/// matching backend used by a guild
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum Backend {
+    /// remote gpt3 searches endpoint
+    #[default]
+    Search,
+    /// local bm25 keyword scoring, works without network access
+    Bm25,
+    /// phrase embeddings compared locally, only the query is embedded remotely
+    Embeddings,
+}
+impl Backend {
+    /// retrieves the matcher implementing this backend
+    pub fn matcher(self) -> &'static dyn Matcher {
+        match self {
+            Backend::Search => &Search,
+            Backend::Bm25 => &Bm25,
+            Backend::Embeddings => &Embeddings,
+        }
+    }
+}
+
+/// name of a model as written in the config
+pub fn model_name(model: &Model) -> String {
+    ron::to_string(model).unwrap_or_default()
+}
+
+/// joins keywords in sorted order, so the text doesn't change with the hash set order
+pub(crate) fn join_keywords(keywords: &HashSet<String>) -> String {
+    let mut keywords = keywords
+        .iter()
+        .map(|keyword| &**keyword)
+        .collect::<Vec<_>>();
+    keywords.sort_unstable();
+    keywords.join(", ")
+}
+
+/// sorts hits by descending score
+pub(crate) fn sort_hits(hits: &mut [Hit]) {
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{data::Phrase, testing::StubApi};
+
+    fn guild(backend: Backend) -> GuildMeta {
+        let mut guild_meta = GuildMeta::default();
+        guild_meta.config.backend = backend;
+        let entry = Phrase {
+            keywords: HashSet::from(["hello there".to_owned(), "greetings".to_owned()]),
+            ..Default::default()
+        };
+        guild_meta
+            .phrases
+            .insert("general kenobi".to_owned(), entry);
+        guild_meta
+    }
+
+    #[tokio::test]
+    async fn search_sends_sorted_keywords() {
+        let api = StubApi::start(&["hello there"]).await;
+        let data = api.data();
+        let mut guild_meta = guild(Backend::Search);
+
+        let hits = score(&data, &mut guild_meta, "hello there").await.unwrap();
+        assert_eq!(hits[0].phrase, "general kenobi");
+        assert_eq!(hits[0].score.round(), 100.0);
+
+        let requests = api.requests();
+        assert_eq!(requests[0].body["documents"][0], "greetings, hello there");
+    }
+
+    #[tokio::test]
+    async fn embeddings_only_embed_changed_phrases() {
+        let api = StubApi::start(&["hello there"]).await;
+        let data = api.data();
+        let mut guild_meta = guild(Backend::Embeddings);
+        guild_meta
+            .phrases
+            .insert("it's over anakin".to_owned(), Phrase::default());
+
+        Embeddings.index(&data, &mut guild_meta).await.unwrap();
+        let requests = api.requests();
+        assert_eq!(requests[0].body["model"], "text-similarity-babbage-001");
+        assert_eq!(requests[0].body["input"].as_array().unwrap().len(), 2);
+
+        // unchanged phrases aren't embedded again
+        Embeddings.index(&data, &mut guild_meta).await.unwrap();
+        assert!(api.requests().is_empty());
+
+        guild_meta
+            .phrases
+            .get_mut("general kenobi")
+            .unwrap()
+            .keywords
+            .insert("hi".to_owned());
+        Embeddings.index(&data, &mut guild_meta).await.unwrap();
+        let requests = api.requests();
+        assert_eq!(
+            requests[0].body["input"][0],
+            "general kenobi, greetings, hello there, hi"
+        );
+        assert_eq!(requests[0].body["input"].as_array().unwrap().len(), 1);
+
+        // a new model embeds everything again
+        guild_meta.config.model_name = Some("custom".to_owned());
+        Embeddings.index(&data, &mut guild_meta).await.unwrap();
+        assert_eq!(api.requests()[0].body["input"].as_array().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::{HashMap, HashSet};
+
+use poise::BoxFuture;
+
+use super::{sort_hits, Hit, Matcher};
+use crate::{
+    data::{Config, Data, GuildMeta, Phrase},
+    Error,
+};
+
+/// term frequency saturation
+const K1: f64 = 1.2;
+/// document length normalization
+const B: f64 = 0.75;
+
+/// scores phrases locally with bm25 over their text and keywords
+///
+/// scores are scaled to `0..=100`, where 100 means the query contains every term of the phrase
+pub struct Bm25;
+
+impl Matcher for Bm25 {
+    fn rank<'a>(
+        &'a self,
+        _data: &'a Data,
+        guild_meta: &'a GuildMeta,
+        query: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<Hit>, Error>> {
+        Box::pin(async move {
+            let documents = guild_meta
+                .phrases
+                .iter()
+                .map(|(phrase, Phrase { keywords, .. })| {
+                    let mut terms = tokenize(phrase);
+                    terms.extend(keywords.iter().flat_map(|keyword| tokenize(keyword)));
+                    (phrase, terms)
+                })
+                .collect::<Vec<_>>();
+
+            let index = Index::new(documents.iter().map(|(_, terms)| &**terms));
+            let query = tokenize(query).into_iter().collect::<HashSet<_>>();
+
+            let mut hits = documents
+                .iter()
+                .map(|(phrase, terms)| Hit {
+                    phrase: (*phrase).clone(),
+                    score: index.score(&query, terms),
+                })
+                .collect::<Vec<_>>();
+            sort_hits(&mut hits);
+
+            Ok(hits)
+        })
+    }
+    fn supports(&self, _config: &Config) -> bool {
+        true
+    }
+    fn cost(&self, _config: &Config) -> String {
+        "free, runs locally and ignores the model".to_owned()
+    }
+}
+
+/// document frequencies of a set of documents
+struct Index {
+    len: usize,
+    avg_len: f64,
+    frequencies: HashMap<String, usize>,
+}
+impl Index {
+    fn new<'a>(documents: impl Iterator<Item = &'a [String]>) -> Self {
+        let mut len = 0;
+        let mut total_len = 0;
+        let mut frequencies = HashMap::<String, usize>::new();
+
+        for terms in documents {
+            len += 1;
+            total_len += terms.len();
+            for term in terms.iter().collect::<HashSet<_>>() {
+                *frequencies.entry(term.clone()).or_default() += 1;
+            }
+        }
+
+        Self {
+            len,
+            avg_len: total_len as f64 / len.max(1) as f64,
+            frequencies,
+        }
+    }
+    /// inverse document frequency of a term
+    fn idf(&self, term: &str) -> f64 {
+        let n = self.frequencies.get(term).copied().unwrap_or_default() as f64;
+        ((self.len as f64 - n + 0.5) / (n + 0.5) + 1.0).ln()
+    }
+    /// bm25 weight of each distinct term of a document
+    fn weights<'a>(&'a self, terms: &'a [String]) -> impl Iterator<Item = (&'a str, f64)> + 'a {
+        let mut counts = HashMap::<&str, usize>::new();
+        for term in terms {
+            *counts.entry(term.as_str()).or_default() += 1;
+        }
+        let norm = 1.0 - B + B * terms.len() as f64 / self.avg_len.max(1.0);
+
+        counts.into_iter().map(move |(term, count)| {
+            let tf = count as f64;
+            (term, self.idf(term) * tf * (K1 + 1.0) / (tf + K1 * norm))
+        })
+    }
+    /// scores the query against a document, relative to the document matching itself
+    fn score(&self, query: &HashSet<String>, terms: &[String]) -> f64 {
+        let (score, max) = self
+            .weights(terms)
+            .fold((0.0, 0.0), |(score, max), (term, weight)| {
+                if query.contains(term) {
+                    (score + weight, max + weight)
+                } else {
+                    (score, max + weight)
+                }
+            });
+
+        if max > 0.0 {
+            score / max * 100.0
+        } else {
+            0.0
+        }
+    }
+}
+
+/// splits text into lowercase alphanumeric terms
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn query(text: &str) -> HashSet<String> {
+        tokenize(text).into_iter().collect()
+    }
+    fn guild(phrases: &[(&str, &[&str])]) -> GuildMeta {
+        let mut guild_meta = GuildMeta::default();
+        for (phrase, keywords) in phrases {
+            let entry = Phrase {
+                keywords: keywords
+                    .iter()
+                    .map(|keyword| (*keyword).to_owned())
+                    .collect(),
+                ..Default::default()
+            };
+            guild_meta.phrases.insert((*phrase).to_owned(), entry);
+        }
+        guild_meta
+    }
+
+    #[test]
+    fn tokenizes_lowercase_alphanumeric_terms() {
+        assert_eq!(
+            tokenize("Hello there, General Kenobi!"),
+            ["hello", "there", "general", "kenobi"]
+        );
+        assert!(tokenize("?! ...").is_empty());
+    }
+
+    #[test]
+    fn scores_relative_to_the_document() {
+        let documents = [
+            tokenize("general kenobi hello there"),
+            tokenize("high ground"),
+        ];
+        let index = Index::new(documents.iter().map(|terms| &**terms));
+
+        let exact = index.score(&query("hello there general kenobi"), &documents[0]);
+        let unrelated = index.score(&query("nice weather"), &documents[0]);
+        let partial = index.score(&query("well hello"), &documents[0]);
+        assert!((exact - 100.0).abs() < 1e-9, "{exact}");
+        assert_eq!(unrelated, 0.0);
+        assert!(partial > 0.0 && partial < 100.0, "{partial}");
+        // more shared terms score higher
+        let more = index.score(&query("well hello there"), &documents[0]);
+        assert!(more > partial, "{more} <= {partial}");
+    }
+
+    #[test]
+    fn empty_documents_score_zero() {
+        let documents = [Vec::new(), tokenize("high ground")];
+        let index = Index::new(documents.iter().map(|terms| &**terms));
+        assert_eq!(index.score(&query("high ground"), &documents[0]), 0.0);
+
+        let index = Index::new(std::iter::empty::<&[String]>());
+        assert_eq!(index.score(&query("anything"), &[]), 0.0);
+    }
+
+    #[tokio::test]
+    async fn ranks_phrases_by_score() {
+        let data = testing::data();
+        let guild_meta = guild(&[
+            ("general kenobi", &["hello there"]),
+            ("it's over anakin", &["high ground"]),
+            ("?!", &[]),
+        ]);
+
+        let hits = Bm25
+            .rank(&data, &guild_meta, "I have the high ground")
+            .await
+            .unwrap();
+        assert_eq!(hits.len(), 3);
+        assert_eq!(hits[0].phrase, "it's over anakin");
+        assert!(hits[0].score > 0.0 && hits[0].score < 100.0);
+        assert!(hits.iter().skip(1).all(|hit| hit.score == 0.0));
+
+        let hits = Bm25
+            .rank(
+                &data,
+                &guild_meta,
+                "it's over anakin, I have the high ground",
+            )
+            .await
+            .unwrap();
+        assert!((hits[0].score - 100.0).abs() < 1e-9, "{}", hits[0].score);
+        assert!(hits.iter().all(|hit| (0.0..=100.0).contains(&hit.score)));
+    }
+
+    #[tokio::test]
+    async fn ranks_nothing_without_phrases() {
+        let hits = Bm25
+            .rank(&testing::data(), &GuildMeta::default(), "hello there")
+            .await
+            .unwrap();
+        assert!(hits.is_empty());
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+
+use gpt3_rs::Model;
+use log::debug;
+use poise::BoxFuture;
+use serde::{Deserialize, Serialize};
+
+use super::{join_keywords, sort_hits, Hit, Matcher};
+use crate::{
+    data::{Config, Data, GuildMeta, Phrase},
+    Error,
+};
+
+/// scores phrases by cosine similarity against precomputed embeddings
+///
+/// scores are the similarity scaled to `0..=100`
+pub struct Embeddings;
+
+/// embedded phrases of a guild, stored with the guild data
+#[derive(Default, Serialize, Deserialize)]
+pub struct EmbeddingIndex {
+    /// model the vectors were created with
+    pub model: Option<String>,
+    pub vectors: HashMap<String, Embedding>,
+}
+#[derive(Serialize, Deserialize)]
+pub struct Embedding {
+    /// embedded text, used to detect changed keywords
+    pub document: String,
+    pub vector: Vec<f32>,
+}
+
+impl Matcher for Embeddings {
+    fn index<'a>(
+        &'a self,
+        data: &'a Data,
+        guild_meta: &'a mut GuildMeta,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let model = model_name(&guild_meta.config)?.to_owned();
+            let index = &mut guild_meta.embeddings;
+
+            // re-embed everything if the model changed
+            if index.model.as_ref() != Some(&model) {
+                index.vectors.clear();
+                index.model = Some(model.clone());
+            }
+
+            let documents = guild_meta
+                .phrases
+                .iter()
+                .map(|(phrase, Phrase { keywords, .. })| {
+                    let document = if keywords.is_empty() {
+                        phrase.clone()
+                    } else {
+                        format!("{phrase}, {}", join_keywords(keywords))
+                    };
+                    (phrase, document)
+                })
+                .collect::<HashMap<_, _>>();
+
+            index
+                .vectors
+                .retain(|phrase, embedding| documents.get(phrase) == Some(&embedding.document));
+
+            let (phrases, documents): (Vec<_>, Vec<_>) = documents
+                .into_iter()
+                .filter(|(phrase, _)| !index.vectors.contains_key(*phrase))
+                .unzip();
+
+            if documents.is_empty() {
+                return Ok(());
+            }
+            debug!("embedding {} phrases", documents.len());
+
+            let vectors = data.client.embeddings(&model, &documents).await?;
+
+            for ((phrase, document), vector) in phrases.into_iter().zip(documents).zip(vectors) {
+                index
+                    .vectors
+                    .insert(phrase.clone(), Embedding { document, vector });
+            }
+
+            Ok(())
+        })
+    }
+    fn rank<'a>(
+        &'a self,
+        data: &'a Data,
+        guild_meta: &'a GuildMeta,
+        query: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<Hit>, Error>> {
+        Box::pin(async move {
+            let index = &guild_meta.embeddings;
+
+            if index.vectors.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            let model = model_name(&guild_meta.config)?;
+            let query = data
+                .client
+                .embeddings(model, &[query.to_owned()])
+                .await?
+                .pop()
+                .ok_or("no embedding returned for query")?;
+
+            let mut hits = guild_meta
+                .phrases
+                .keys()
+                .filter_map(|phrase| {
+                    index.vectors.get(phrase).map(|embedding| Hit {
+                        phrase: phrase.clone(),
+                        score: cosine(&query, &embedding.vector).max(0.0) * 100.0,
+                    })
+                })
+                .collect::<Vec<_>>();
+            sort_hits(&mut hits);
+
+            Ok(hits)
+        })
+    }
+    fn supports(&self, config: &Config) -> bool {
+        model_name(config).is_ok()
+    }
+    fn cost(&self, config: &Config) -> String {
+        match price(config) {
+            Some(price) => format!("{price} / 1k tokens of query, phrases are embedded once"),
+            None => "unknown".to_owned(),
+        }
+    }
+}
+
+/// embedding model used for a config
+fn model_name(config: &Config) -> Result<&str, Error> {
+    if let Some(model_name) = &config.model_name {
+        return Ok(model_name);
+    }
+    match config.model {
+        Model::Ada => Ok("text-similarity-ada-001"),
+        Model::Babbage => Ok("text-similarity-babbage-001"),
+        Model::Curie => Ok("text-similarity-curie-001"),
+        Model::Davinci => Ok("text-similarity-davinci-001"),
+        _ => Err("model has no embedding variant".into()),
+    }
+}
+
+/// cosine similarity of two vectors
+fn cosine(a: &[f32], b: &[f32]) -> f64 {
+    let (dot, norm_a, norm_b) =
+        a.iter()
+            .zip(b)
+            .fold((0.0, 0.0, 0.0), |(dot, norm_a, norm_b), (&a, &b)| {
+                let (a, b) = (a as f64, b as f64);
+                (dot + a * b, norm_a + a * a, norm_b + b * b)
+            });
+
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a.sqrt() * norm_b.sqrt())
+    }
+}
+
+/// embedding price of the model of a config per 1k tokens, unknown for custom model names
+///
+/// the similarity models are billed higher than searches with the same model
+fn price(config: &Config) -> Option<&'static str> {
+    if config.model_name.is_some() {
+        return None;
+    }
+    match config.model {
+        Model::Ada => Some("$0.004"),
+        Model::Babbage => Some("$0.005"),
+        Model::Curie => Some("$0.02"),
+        Model::Davinci => Some("$0.2"),
+        _ => None,
+    }
+}
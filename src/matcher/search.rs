@@ -0,0 +1,100 @@
+use gpt3_rs::Model;
+use log::debug;
+use poise::BoxFuture;
+
+use super::{join_keywords, sort_hits, Hit, Matcher};
+use crate::{
+    data::{Config, Data, GuildMeta, Phrase},
+    Error,
+};
+
+/// scores phrases with the gpt3 searches endpoint
+pub struct Search;
+
+impl Matcher for Search {
+    fn rank<'a>(
+        &'a self,
+        data: &'a Data,
+        guild_meta: &'a GuildMeta,
+        query: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<Hit>, Error>> {
+        Box::pin(async move {
+            let phrases = &guild_meta.phrases;
+
+            if phrases.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            let engine = engine(&guild_meta.config).ok_or("model has no search engine")?;
+
+            // searches the keywords of a phrase, or the phrase itself if it has none
+            let (phrases, documents): (Vec<_>, Vec<_>) = phrases
+                .iter()
+                .map(|(phrase, Phrase { keywords, .. })| {
+                    let document = if keywords.is_empty() {
+                        phrase.clone()
+                    } else {
+                        join_keywords(keywords)
+                    };
+                    (phrase, document)
+                })
+                .unzip();
+
+            debug!("documents:\n{:#?}", documents);
+
+            let response = data.client.search(engine, &documents, query).await?;
+
+            debug!("response:\n{:#?}", response);
+
+            let mut hits = response
+                .into_iter()
+                .filter_map(|result| {
+                    phrases.get(result.document).map(|phrase| Hit {
+                        phrase: (*phrase).clone(),
+                        score: result.score,
+                    })
+                })
+                .collect::<Vec<_>>();
+            sort_hits(&mut hits);
+
+            Ok(hits)
+        })
+    }
+    fn supports(&self, config: &Config) -> bool {
+        engine(config).is_some()
+    }
+    fn cost(&self, config: &Config) -> String {
+        match price(config) {
+            Some(price) => format!("{price} / 1k tokens of query and every phrase"),
+            None => "unknown".to_owned(),
+        }
+    }
+}
+
+/// search engine used for a config
+fn engine(config: &Config) -> Option<&str> {
+    if let Some(model_name) = &config.model_name {
+        return Some(model_name);
+    }
+    match config.model {
+        Model::Ada => Some("ada"),
+        Model::Babbage => Some("babbage"),
+        Model::Curie => Some("curie"),
+        Model::Davinci => Some("davinci"),
+        _ => None,
+    }
+}
+
+/// search price of the model of a config per 1k tokens, unknown for custom model names
+fn price(config: &Config) -> Option<&'static str> {
+    if config.model_name.is_some() {
+        return None;
+    }
+    match config.model {
+        Model::Ada => Some("$0.0008"),
+        Model::Babbage => Some("$0.0012"),
+        Model::Curie => Some("$0.006"),
+        Model::Davinci => Some("$0.06"),
+        _ => None,
+    }
+}
@@ -0,0 +1,148 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+
+/// base url of the public api
+pub const DEFAULT_API_URL: &str = "https://api.openai.com/v1";
+
+/// minimal client for openai compatible apis
+pub struct Client {
+    http: reqwest::Client,
+    token: String,
+    base_url: String,
+}
+
+#[derive(Serialize)]
+struct EmbeddingRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<Embedding>,
+}
+#[derive(Deserialize)]
+struct Embedding {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+#[derive(Serialize)]
+struct SearchRequest<'a> {
+    documents: &'a [String],
+    query: &'a str,
+}
+#[derive(Deserialize)]
+struct SearchResponse {
+    data: Vec<SearchResult>,
+}
+/// score of a single document
+#[derive(Debug, Deserialize)]
+pub struct SearchResult {
+    /// index of the document in the request
+    pub document: usize,
+    pub score: f64,
+}
+
+impl Client {
+    pub fn new(token: String, base_url: String) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            token,
+            base_url: base_url.trim_end_matches('/').to_owned(),
+        }
+    }
+    /// embeds every input, in the same order as the inputs
+    pub async fn embeddings(&self, model: &str, input: &[String]) -> Result<Vec<Vec<f32>>, Error> {
+        if input.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut response = self
+            .http
+            .post(format!("{}/embeddings", self.base_url))
+            .bearer_auth(&self.token)
+            .json(&EmbeddingRequest { model, input })
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<EmbeddingResponse>()
+            .await?;
+
+        response.data.sort_by_key(|embedding| embedding.index);
+
+        Ok(response
+            .data
+            .into_iter()
+            .map(|embedding| embedding.embedding)
+            .collect())
+    }
+    /// scores every document against the query
+    pub async fn search(
+        &self,
+        engine: &str,
+        documents: &[String],
+        query: &str,
+    ) -> Result<Vec<SearchResult>, Error> {
+        let response = self
+            .http
+            .post(format!("{}/engines/{engine}/search", self.base_url))
+            .bearer_auth(&self.token)
+            .json(&SearchRequest { documents, query })
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<SearchResponse>()
+            .await?;
+
+        Ok(response.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::StubApi;
+
+    #[tokio::test]
+    async fn embeddings_keep_input_order() {
+        let api = StubApi::start(&["hello there", "high ground"]).await;
+        // a trailing slash of the base url is ignored
+        let client = Client::new("token".to_owned(), format!("{}/", api.url));
+        let input = ["high ground".to_owned(), "hello there".to_owned()];
+
+        let embeddings = client.embeddings("model", &input).await.unwrap();
+        assert_eq!(embeddings, vec![vec![0.0, 1.0], vec![1.0, 0.0]]);
+
+        let requests = api.requests();
+        assert_eq!(requests[0].path, "/embeddings");
+        assert_eq!(requests[0].body["model"], "model");
+    }
+
+    #[tokio::test]
+    async fn embeddings_without_input_skip_the_request() {
+        let api = StubApi::start(&[]).await;
+        let client = Client::new("token".to_owned(), api.url.clone());
+
+        assert!(client.embeddings("model", &[]).await.unwrap().is_empty());
+        assert!(api.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn search_scores_every_document() {
+        let api = StubApi::start(&["hello there", "high ground"]).await;
+        let client = Client::new("token".to_owned(), api.url.clone());
+        let documents = ["high ground".to_owned(), "hello there".to_owned()];
+
+        let results = client
+            .search("ada", &documents, "well hello there")
+            .await
+            .unwrap();
+        let scores = results
+            .iter()
+            .map(|result| (result.document, result.score.round()))
+            .collect::<Vec<_>>();
+        assert_eq!(scores, vec![(0, 0.0), (1, 100.0)]);
+        assert_eq!(api.requests()[0].path, "/engines/ada/search");
+    }
+}
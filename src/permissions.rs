@@ -0,0 +1,120 @@
+use std::collections::HashSet;
+
+use poise::serenity_prelude::{self as serenity, RoleId};
+use serde::{Deserialize, Serialize};
+
+use crate::{Context, Error};
+
+/// group of management commands with its own access rules
+#[derive(Clone, Copy, Debug, poise::SlashChoiceParameter)]
+pub enum Area {
+    #[name = "phrases"]
+    Phrases,
+    #[name = "config"]
+    Config,
+    #[name = "channels"]
+    Channels,
+}
+
+/// who may use the management commands of a guild
+#[derive(Default, Serialize, Deserialize)]
+pub struct AccessControl {
+    pub phrases: Access,
+    pub config: Access,
+    pub channels: Access,
+}
+impl AccessControl {
+    pub fn get(&self, area: Area) -> &Access {
+        match area {
+            Area::Phrases => &self.phrases,
+            Area::Config => &self.config,
+            Area::Channels => &self.channels,
+        }
+    }
+    pub fn get_mut(&mut self, area: Area) -> &mut Access {
+        match area {
+            Area::Phrases => &mut self.phrases,
+            Area::Config => &mut self.config,
+            Area::Channels => &mut self.channels,
+        }
+    }
+}
+
+/// members with any of the roles or any of the permissions are allowed
+#[derive(Serialize, Deserialize)]
+pub struct Access {
+    pub roles: HashSet<RoleId>,
+    pub permissions: serenity::Permissions,
+}
+impl Default for Access {
+    fn default() -> Self {
+        Self {
+            roles: HashSet::new(),
+            permissions: serenity::Permissions::MANAGE_GUILD,
+        }
+    }
+}
+
+/// checks if the author is a bot owner, bot owners may use every command
+fn is_owner(ctx: Context<'_>) -> bool {
+    ctx.framework().options().owners.contains(&ctx.author().id)
+}
+
+/// retrieves the guild permissions of the author
+async fn author_permissions(
+    ctx: Context<'_>,
+) -> Result<Option<(serenity::Member, serenity::Permissions)>, Error> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(None)
+    };
+    let member = guild_id.member(ctx.discord(), ctx.author().id).await?;
+    let permissions = member.permissions(ctx.discord())?;
+
+    Ok(Some((member, permissions)))
+}
+
+/// checks the access rules of an area for the author
+async fn allowed(ctx: Context<'_>, area: Area) -> Result<bool, Error> {
+    if is_owner(ctx) {
+        return Ok(true);
+    }
+    let Some((member, permissions)) = author_permissions(ctx).await? else {
+        return Ok(false)
+    };
+
+    let guild_meta_lock = ctx
+        .data()
+        .get_guild(member.guild_id)
+        .await
+        .expect("guild not found");
+    let guild_meta = guild_meta_lock.read().await;
+    let access = guild_meta.access.get(area);
+
+    Ok(permissions.intersects(access.permissions)
+        || member.roles.iter().any(|role| access.roles.contains(role)))
+}
+
+/// check for commands editing phrases
+pub async fn manage_phrases(ctx: Context<'_>) -> Result<bool, Error> {
+    allowed(ctx, Area::Phrases).await
+}
+
+/// check for commands editing the config
+pub async fn manage_config(ctx: Context<'_>) -> Result<bool, Error> {
+    allowed(ctx, Area::Config).await
+}
+
+/// check for commands editing channels
+pub async fn manage_channels(ctx: Context<'_>) -> Result<bool, Error> {
+    allowed(ctx, Area::Channels).await
+}
+
+/// check for commands editing the access rules, always requires the manage server permission
+pub async fn manage_access(ctx: Context<'_>) -> Result<bool, Error> {
+    if is_owner(ctx) {
+        return Ok(true);
+    }
+    Ok(author_permissions(ctx)
+        .await?
+        .is_some_and(|(_, permissions)| permissions.manage_guild()))
+}
@@ -0,0 +1,232 @@
+use std::collections::{HashMap, HashSet};
+
+use poise::serenity_prelude::ChannelId;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    data::{Channel, Config, GuildMeta, Phrase},
+    matcher::EmbeddingIndex,
+    permissions::AccessControl,
+    Error,
+};
+
+/// version of the stored guild format, bump it and add a migration when the format changes
+pub const VERSION: u32 = 3;
+
+/// guild data as stored, tagged with the format version
+#[derive(Serialize, Deserialize)]
+pub struct Stored<T> {
+    pub version: u32,
+    pub guild: T,
+}
+impl<'a> Stored<&'a GuildMeta> {
+    /// tags a guild with the current version
+    pub fn current(guild: &'a GuildMeta) -> Self {
+        Self {
+            version: VERSION,
+            guild,
+        }
+    }
+}
+
+/// the version tag alone, files without one are version 0
+#[derive(Deserialize)]
+struct Header {
+    #[serde(default)]
+    version: u32,
+}
+
+/// a decoded guild
+pub struct Decoded {
+    pub guild_meta: GuildMeta,
+    /// version the guild was migrated from, if it was stored with an older version
+    pub migrated_from: Option<u32>,
+}
+
+/// decodes a stored guild of any known version
+pub fn decode(text: &str) -> Result<Decoded, Error> {
+    let version = ron::from_str::<Header>(text)?.version;
+    let guild_meta = migrate(version, text)?;
+
+    Ok(Decoded {
+        guild_meta,
+        migrated_from: (version != VERSION).then_some(version),
+    })
+}
+
+/// parses a guild stored with `version` and migrates it step by step to the current format
+fn migrate(version: u32, text: &str) -> Result<GuildMeta, Error> {
+    match version {
+        // unversioned guilds were stored without the `Stored` wrapper
+        0 => Ok(V2::from(ron::from_str::<V1>(text)?).into()),
+        1 => Ok(V2::from(ron::from_str::<Stored<V1>>(text)?.guild).into()),
+        2 => Ok(ron::from_str::<Stored<V2>>(text)?.guild.into()),
+        VERSION => Ok(ron::from_str::<Stored<GuildMeta>>(text)?.guild),
+        _ => Err(format!("unknown schema version {version}, newest is {VERSION}").into()),
+    }
+}
+
+/// guild format of versions 0 and 1, channels had no settings of their own
+#[derive(Deserialize)]
+struct V1 {
+    phrases: HashMap<String, HashSet<String>>,
+    channels: HashSet<ChannelId>,
+    config: Config,
+    #[serde(default)]
+    embeddings: EmbeddingIndex,
+    #[serde(default)]
+    access: AccessControl,
+}
+impl From<V1> for V2 {
+    fn from(old: V1) -> Self {
+        Self {
+            phrases: old.phrases,
+            channels: old
+                .channels
+                .into_iter()
+                .map(|channel| (channel, Default::default()))
+                .collect(),
+            config: old.config,
+            embeddings: old.embeddings,
+            access: old.access,
+        }
+    }
+}
+
+/// guild format of version 2, phrases were stored as their keywords alone
+#[derive(Deserialize)]
+struct V2 {
+    phrases: HashMap<String, HashSet<String>>,
+    channels: HashMap<ChannelId, Channel>,
+    config: Config,
+    #[serde(default)]
+    embeddings: EmbeddingIndex,
+    #[serde(default)]
+    access: AccessControl,
+}
+impl From<V2> for GuildMeta {
+    fn from(old: V2) -> Self {
+        Self {
+            phrases: old
+                .phrases
+                .into_iter()
+                .map(|(phrase, keywords)| {
+                    let phrase_entry = Phrase {
+                        keywords,
+                        ..Default::default()
+                    };
+                    (phrase, phrase_entry)
+                })
+                .collect(),
+            channels: old.channels,
+            config: old.config,
+            embeddings: old.embeddings,
+            access: old.access,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHANNEL: ChannelId = ChannelId(123);
+
+    /// a config as the baseline stored it, before any of the later fields
+    fn baseline_config() -> String {
+        let model = ron::to_string(&gpt3_rs::Model::Babbage).unwrap();
+        format!(
+            "(minimum_score: 30, max_context_len: 256, chance: 50, cooldown: 90, model: {model})"
+        )
+    }
+    fn channel() -> String {
+        ron::to_string(&CHANNEL).unwrap()
+    }
+    fn assert_migrated(decoded: &Decoded, version: u32) {
+        let guild_meta = &decoded.guild_meta;
+        assert_eq!(decoded.migrated_from, Some(version));
+
+        let phrase = &guild_meta.phrases["general kenobi"];
+        assert_eq!(phrase.keywords, HashSet::from(["hello there".to_owned()]));
+        assert!(
+            phrase
+                == &Phrase {
+                    keywords: phrase.keywords.clone(),
+                    ..Default::default()
+                }
+        );
+        assert!(guild_meta.channels.contains_key(&CHANNEL));
+
+        let config = &guild_meta.config;
+        assert_eq!(config.minimum_score, 30);
+        assert_eq!(config.max_context_len, 256);
+        assert_eq!(config.chance, 50);
+        assert_eq!(config.cooldown, 90);
+        // fields added later take their defaults
+        let defaults = Config::default();
+        assert_eq!(config.phrase_cooldown, defaults.phrase_cooldown);
+        assert_eq!(config.user_budget_window, defaults.user_budget_window);
+        assert_eq!(config.guild_cooldown, None);
+    }
+
+    #[test]
+    fn decodes_version_0() {
+        let text = format!(
+            r#"(phrases: {{"general kenobi": ["hello there"]}}, channels: [{}], config: {})"#,
+            channel(),
+            baseline_config(),
+        );
+        assert_migrated(&decode(&text).unwrap(), 0);
+    }
+
+    #[test]
+    fn decodes_version_1() {
+        let text = format!(
+            r#"(version: 1, guild: (phrases: {{"general kenobi": ["hello there"]}}, channels: [{}], config: {}))"#,
+            channel(),
+            baseline_config(),
+        );
+        assert_migrated(&decode(&text).unwrap(), 1);
+    }
+
+    #[test]
+    fn decodes_version_2() {
+        let text = format!(
+            r#"(version: 2, guild: (phrases: {{"general kenobi": ["hello there"]}}, channels: {{{}: (overrides: {{"chance": "10"}})}}, config: {}))"#,
+            channel(),
+            baseline_config(),
+        );
+        let decoded = decode(&text).unwrap();
+        assert_migrated(&decoded, 2);
+        assert_eq!(decoded.guild_meta.channel_config(CHANNEL).chance, 10);
+    }
+
+    #[test]
+    fn decodes_current_version() {
+        let mut guild_meta = GuildMeta::default();
+        guild_meta.channels.insert(CHANNEL, Channel::default());
+        guild_meta
+            .phrases
+            .insert("general kenobi".to_owned(), Phrase::default());
+        let text = ron::to_string(&Stored::current(&guild_meta)).unwrap();
+
+        let decoded = decode(&text).unwrap();
+        assert_eq!(decoded.migrated_from, None);
+        assert!(decoded.guild_meta.channels.contains_key(&CHANNEL));
+        assert!(decoded.guild_meta.phrases.contains_key("general kenobi"));
+    }
+
+    #[test]
+    fn rejects_future_version() {
+        let text = format!(
+            "(version: {}, guild: (phrases: {{}}, channels: {{}}, config: {}))",
+            VERSION + 1,
+            baseline_config(),
+        );
+        let Err(err) = decode(&text) else {
+            panic!("decoded a guild of a future version")
+        };
+        assert!(err.to_string().contains("unknown schema version"));
+    }
+}
@@ -0,0 +1,175 @@
+use std::{collections::HashSet, env, path::PathBuf};
+
+use poise::serenity_prelude::UserId;
+use serde::Deserialize;
+
+use crate::{data::Config, matcher, openai, Error};
+
+/// settings file read when `CATCHPHRASE_CONFIG` isn't set
+const DEFAULT_PATH: &str = "catchphrase.ron";
+
+/// where guild data is stored
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub enum StorageKind {
+    /// one ron file per guild in `data_path`
+    Ron,
+    /// an sqlite database at `<data_path>.sqlite`
+    Sqlite,
+}
+
+/// bot wide settings
+///
+/// read from a ron file, every missing field uses its default,
+/// and most fields can be overridden with environment variables
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// discord bot token, `GPT_BOT_TOKEN`
+    pub bot_token: Option<String>,
+    /// api token for the remote backends, `GPT_API_TOKEN`
+    pub api_token: Option<String>,
+    /// base url of the openai compatible api, `GPT_API_URL`
+    pub api_url: String,
+    /// users allowed to use every command, `CATCHPHRASE_OWNERS` as comma separated ids,
+    /// nobody by default
+    pub owners: HashSet<UserId>,
+    /// prefix of prefix commands, `CATCHPHRASE_PREFIX`
+    pub prefix: String,
+    /// directory or database path of guild data, `CATCHPHRASE_DATA_PATH`
+    pub data_path: String,
+    /// `CATCHPHRASE_STORAGE` as `ron` or `sqlite`
+    pub storage: StorageKind,
+    pub embed_color: u32,
+    /// env_logger filter, `CATCHPHRASE_LOG`
+    pub log_filter: String,
+    /// config of guilds without stored data
+    pub guild_defaults: Config,
+}
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            bot_token: None,
+            api_token: None,
+            api_url: openai::DEFAULT_API_URL.to_owned(),
+            owners: HashSet::new(),
+            prefix: "!".to_owned(),
+            data_path: "guilds".to_owned(),
+            storage: StorageKind::Ron,
+            embed_color: 0x2F3136,
+            log_filter: "catchphrase=debug".to_owned(),
+            guild_defaults: Config::default(),
+        }
+    }
+}
+
+impl Settings {
+    /// reads the settings file and applies environment overrides
+    ///
+    /// a missing file is only an error if its path was given explicitly
+    pub fn load() -> Result<Self, Error> {
+        let (path, explicit) = match env::var("CATCHPHRASE_CONFIG") {
+            Ok(path) => (PathBuf::from(path), true),
+            Err(_) => (PathBuf::from(DEFAULT_PATH), false),
+        };
+
+        let mut settings = match std::fs::read_to_string(&path) {
+            Ok(text) => ron::from_str::<Settings>(&text)
+                .map_err(|err| format!("invalid settings file {}: {}", path.display(), err))?,
+            Err(err) if !explicit && err.kind() == std::io::ErrorKind::NotFound => {
+                Settings::default()
+            }
+            Err(err) => {
+                return Err(format!("can't read settings file {}: {}", path.display(), err).into())
+            }
+        };
+
+        if let Ok(token) = env::var("GPT_BOT_TOKEN") {
+            settings.bot_token = Some(token);
+        }
+        if let Ok(token) = env::var("GPT_API_TOKEN") {
+            settings.api_token = Some(token);
+        }
+        if let Ok(api_url) = env::var("GPT_API_URL") {
+            settings.api_url = api_url;
+        }
+        if let Ok(owners) = env::var("CATCHPHRASE_OWNERS") {
+            settings.owners = owners
+                .split(',')
+                .map(|owner| owner.trim().parse().map(UserId))
+                .collect::<Result<_, _>>()
+                .map_err(|err| format!("invalid CATCHPHRASE_OWNERS: {}", err))?;
+        }
+        if let Ok(prefix) = env::var("CATCHPHRASE_PREFIX") {
+            settings.prefix = prefix;
+        }
+        if let Ok(data_path) = env::var("CATCHPHRASE_DATA_PATH") {
+            settings.data_path = data_path;
+        }
+        if let Ok(storage) = env::var("CATCHPHRASE_STORAGE") {
+            settings.storage = match &*storage {
+                "ron" => StorageKind::Ron,
+                "sqlite" => StorageKind::Sqlite,
+                _ => return Err(format!("invalid CATCHPHRASE_STORAGE: {}", storage).into()),
+            };
+        }
+        if let Ok(log_filter) = env::var("CATCHPHRASE_LOG") {
+            settings.log_filter = log_filter;
+        }
+
+        check_guild_defaults(&settings.guild_defaults)
+            .map_err(|err| format!("invalid guild_defaults: {}", err))?;
+
+        if settings.bot_token.is_none() {
+            return Err(
+                "no bot token, set `bot_token` in the settings file or GPT_BOT_TOKEN".into(),
+            );
+        }
+
+        Ok(settings)
+    }
+}
+
+/// checks the config every new guild starts with, like `/config set` checks guild configs
+fn check_guild_defaults(config: &Config) -> Result<(), String> {
+    config.validate()?;
+    if !config.backend.matcher().supports(config) {
+        return Err(format!(
+            "model `{}` is not supported by backend `{:?}`",
+            matcher::model_name(&config.model),
+            config.backend
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Selection;
+
+    #[test]
+    fn accepts_default_guild_defaults() {
+        assert!(check_guild_defaults(&Config::default()).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_guild_defaults() {
+        let invalid = [
+            Config {
+                chance: 250,
+                ..Default::default()
+            },
+            Config {
+                cooldown: 0,
+                ..Default::default()
+            },
+            Config {
+                selection: Selection::TopK(0),
+                ..Default::default()
+            },
+        ];
+        for config in invalid {
+            assert!(check_guild_defaults(&config).is_err());
+        }
+    }
+}
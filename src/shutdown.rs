@@ -0,0 +1,52 @@
+use std::{sync::Arc, time::Duration};
+
+use log::{info, warn};
+use poise::serenity_prelude::{Mutex, ShardManager};
+
+use crate::data::Data;
+
+/// maximum time to wait for in-flight events before shutting down anyway
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// waits for SIGINT, or SIGTERM on unix
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+                return;
+            }
+            Err(err) => warn!("error installing SIGTERM handler: {}", err),
+        }
+    }
+
+    if let Err(err) = tokio::signal::ctrl_c().await {
+        warn!("error waiting for SIGINT: {}", err);
+        std::future::pending::<()>().await;
+    }
+}
+
+/// stops taking new events, waits for in-flight ones and disconnects all shards
+pub async fn shutdown(data: &Data, shard_manager: Arc<Mutex<ShardManager>>) {
+    info!("shutting down");
+    data.stop_events();
+    drain(data).await;
+
+    shard_manager.lock().await.shutdown_all().await;
+}
+
+/// waits up to `DRAIN_TIMEOUT` for in-flight events
+pub async fn drain(data: &Data) {
+    if tokio::time::timeout(DRAIN_TIMEOUT, data.drained())
+        .await
+        .is_err()
+    {
+        warn!("in-flight events did not finish in time");
+    }
+}
@@ -0,0 +1,109 @@
+mod files;
+mod sqlite;
+
+use std::{sync::Arc, time::Duration};
+
+use log::{debug, warn};
+use poise::{serenity_prelude::GuildId, BoxFuture};
+use tokio::sync::oneshot;
+
+use crate::{
+    data::{Data, GuildMeta},
+    Error,
+};
+
+pub use files::RonFiles;
+pub use sqlite::Sqlite;
+
+/// time to wait after a change before saving, so bursts of changes are written once
+const AUTOSAVE_DELAY: Duration = Duration::from_secs(5);
+
+/// persists guild data
+pub trait Storage: Send + Sync {
+    /// loads a single guild, `None` if it was never saved
+    fn load(&self, guild_id: GuildId) -> BoxFuture<'_, Result<Option<GuildMeta>, Error>>;
+    /// saves a single guild, replacing what was stored before
+    fn save<'a>(
+        &'a self,
+        guild_id: GuildId,
+        guild_meta: &'a GuildMeta,
+    ) -> BoxFuture<'a, Result<(), Error>>;
+    /// removes a guild
+    fn delete(&self, guild_id: GuildId) -> BoxFuture<'_, Result<(), Error>>;
+    /// ids of every stored guild
+    fn list(&self) -> BoxFuture<'_, Result<Vec<GuildId>, Error>>;
+    /// loads every stored guild, guilds that fail to load are skipped with a warning
+    fn load_all(&self) -> BoxFuture<'_, Result<Vec<(GuildId, GuildMeta)>, Error>> {
+        Box::pin(async move {
+            let mut guilds = Vec::new();
+
+            for guild_id in self.list().await? {
+                match self.load(guild_id).await {
+                    Ok(Some(guild_meta)) => guilds.push((guild_id, guild_meta)),
+                    Ok(None) => {}
+                    Err(err) => warn!("error loading guild {}: {}", guild_id, err),
+                }
+            }
+
+            Ok(guilds)
+        })
+    }
+}
+
+/// saves every guild marked as dirty, returns the number of guilds that failed to save
+pub async fn save_dirty(data: &Data) -> u32 {
+    let _saving = data.saving.lock().await;
+    let guild_ids = data.take_dirty();
+    save_guilds(data, guild_ids).await
+}
+
+/// saves every guild, returns the number of guilds that failed to save
+pub async fn save_all(data: &Data) -> u32 {
+    let _saving = data.saving.lock().await;
+    let guild_ids = data.guild_meta_map.read().await.keys().copied().collect();
+    data.take_dirty();
+    save_guilds(data, guild_ids).await
+}
+
+async fn save_guilds(data: &Data, guild_ids: Vec<GuildId>) -> u32 {
+    let mut errors = 0;
+
+    for guild_id in guild_ids {
+        let Some(guild_meta_lock) = data.get_guild(guild_id).await else {
+            continue
+        };
+        let guild_meta = guild_meta_lock.read().await;
+
+        match data.storage.save(guild_id, &guild_meta).await {
+            Ok(_) => {
+                debug!("saved guild: {}", guild_id);
+            }
+            Err(err) => {
+                warn!("error while saving guild: {}", guild_id);
+                warn!("error: `{}`", err);
+                // retry with the next save
+                data.mark_dirty(guild_id);
+                errors += 1;
+            }
+        }
+    }
+
+    errors
+}
+
+/// saves dirty guilds shortly after they change, until `stop` fires
+///
+/// a save that already started is finished before returning, only the waiting is cut short
+pub async fn autosave(data: Arc<Data>, mut stop: oneshot::Receiver<()>) {
+    loop {
+        let wait = async {
+            data.changed.notified().await;
+            tokio::time::sleep(AUTOSAVE_DELAY).await;
+        };
+        tokio::select! {
+            _ = wait => {}
+            _ = &mut stop => return,
+        }
+        save_dirty(&data).await;
+    }
+}
@@ -0,0 +1,194 @@
+use std::path::PathBuf;
+
+use log::{error, info};
+use poise::{serenity_prelude::GuildId, BoxFuture};
+use tokio::io::AsyncWriteExt;
+
+use super::Storage;
+use crate::{
+    data::GuildMeta,
+    schema::{self, Stored},
+    Error,
+};
+
+/// stores each guild as `<id>.ron` in a directory
+///
+/// files of older schema versions are backed up to `<id>.ron.v<version>.bak` before being
+/// rewritten, files that fail to load are moved to `quarantine/` instead of being overwritten
+pub struct RonFiles {
+    path: PathBuf,
+}
+impl RonFiles {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+    fn guild_path(&self, guild_id: GuildId) -> PathBuf {
+        self.path.join(format!("{}.ron", guild_id.0))
+    }
+    /// moves a guild file out of the way so it is kept for manual recovery
+    async fn quarantine(&self, guild_id: GuildId) -> Result<(), Error> {
+        let quarantine = self.path.join("quarantine");
+        tokio::fs::create_dir_all(&quarantine).await?;
+        tokio::fs::rename(
+            self.guild_path(guild_id),
+            quarantine.join(format!("{}.ron", guild_id.0)),
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl Storage for RonFiles {
+    fn load(&self, guild_id: GuildId) -> BoxFuture<'_, Result<Option<GuildMeta>, Error>> {
+        Box::pin(async move {
+            let path = self.guild_path(guild_id);
+            let text = match tokio::fs::read_to_string(&path).await {
+                Ok(text) => text,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                Err(err) => return Err(err.into()),
+            };
+
+            let decoded = match schema::decode(&text) {
+                Ok(decoded) => decoded,
+                Err(err) => {
+                    error!("quarantining guild {}: {}", guild_id, err);
+                    self.quarantine(guild_id).await?;
+                    return Err(err);
+                }
+            };
+
+            // keep the original and rewrite the file in the current format
+            if let Some(version) = decoded.migrated_from {
+                info!("migrating guild {} from version {}", guild_id, version);
+                tokio::fs::copy(&path, path.with_extension(format!("ron.v{version}.bak"))).await?;
+                self.save(guild_id, &decoded.guild_meta).await?;
+            }
+
+            Ok(Some(decoded.guild_meta))
+        })
+    }
+    /// writes to a temporary file first, syncs it to disk and renames it over the old file,
+    /// so a crash or power loss mid write can't leave a corrupted or empty guild file behind
+    fn save<'a>(
+        &'a self,
+        guild_id: GuildId,
+        guild_meta: &'a GuildMeta,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let text =
+                ron::ser::to_string_pretty(&Stored::current(guild_meta), Default::default())?;
+
+            tokio::fs::create_dir_all(&self.path).await?;
+            let path = self.guild_path(guild_id);
+            let temp_path = path.with_extension("ron.tmp");
+
+            let mut file = tokio::fs::File::create(&temp_path).await?;
+            file.write_all(text.as_bytes()).await?;
+            file.sync_all().await?;
+            drop(file);
+
+            tokio::fs::rename(&temp_path, &path).await?;
+            // the rename is only durable once the directory is synced
+            tokio::fs::File::open(&self.path).await?.sync_all().await?;
+
+            Ok(())
+        })
+    }
+    fn delete(&self, guild_id: GuildId) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            match tokio::fs::remove_file(self.guild_path(guild_id)).await {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+                _ => Ok(()),
+            }
+        })
+    }
+    fn list(&self) -> BoxFuture<'_, Result<Vec<GuildId>, Error>> {
+        Box::pin(async move {
+            let mut guild_files = match tokio::fs::read_dir(&self.path).await {
+                Ok(guild_files) => guild_files,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+                Err(err) => return Err(err.into()),
+            };
+
+            let mut guild_ids = Vec::new();
+            while let Some(guild_file) = guild_files.next_entry().await? {
+                let file_name = guild_file.file_name();
+                let file_name = file_name.to_string_lossy();
+
+                // get id from file name, skipping anything that isn't a guild file
+                if let Some(Ok(id)) = file_name.strip_suffix(".ron").map(str::parse::<u64>) {
+                    guild_ids.push(GuildId(id));
+                }
+            }
+
+            Ok(guild_ids)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::testing::{self, TempDir};
+
+    const GUILD: GuildId = GuildId(1);
+
+    #[tokio::test]
+    async fn saves_loads_lists_and_deletes() {
+        let dir = TempDir::new("files-round-trip");
+        let storage = RonFiles::new(dir.path().join("guilds"));
+        // a missing directory has no guilds
+        assert!(storage.list().await.unwrap().is_empty());
+
+        let guild_meta = testing::guild_meta();
+        storage.save(GUILD, &guild_meta).await.unwrap();
+        storage.save(GUILD, &guild_meta).await.unwrap();
+        // files that aren't guild files are ignored
+        std::fs::write(dir.path().join("guilds/notes.txt"), "").unwrap();
+
+        let loaded = storage.load(GUILD).await.unwrap().unwrap();
+        assert!(loaded.phrases == guild_meta.phrases);
+        assert_eq!(storage.list().await.unwrap(), [GUILD]);
+        assert!(!dir.path().join("guilds/1.ron.tmp").exists());
+
+        storage.delete(GUILD).await.unwrap();
+        assert!(storage.load(GUILD).await.unwrap().is_none());
+        assert!(storage.list().await.unwrap().is_empty());
+        // deleting a missing guild is fine
+        storage.delete(GUILD).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn backs_up_migrated_files() {
+        let dir = TempDir::new("files-migrate");
+        let storage = RonFiles::new(dir.path());
+        let text = testing::unversioned_guild();
+        std::fs::write(dir.path().join("1.ron"), &text).unwrap();
+
+        let loaded = storage.load(GUILD).await.unwrap().unwrap();
+        let keywords = HashSet::from(["hello there".to_owned()]);
+        assert_eq!(loaded.phrases["general kenobi"].keywords, keywords);
+
+        let backup = std::fs::read_to_string(dir.path().join("1.ron.v0.bak")).unwrap();
+        assert_eq!(backup, text);
+        // the file was rewritten in the current format
+        let rewritten = std::fs::read_to_string(dir.path().join("1.ron")).unwrap();
+        assert_eq!(schema::decode(&rewritten).unwrap().migrated_from, None);
+    }
+
+    #[tokio::test]
+    async fn quarantines_broken_files() {
+        let dir = TempDir::new("files-quarantine");
+        let storage = RonFiles::new(dir.path());
+        std::fs::write(dir.path().join("1.ron"), "not a guild").unwrap();
+
+        assert!(storage.load(GUILD).await.is_err());
+        assert!(!dir.path().join("1.ron").exists());
+        let quarantined = std::fs::read_to_string(dir.path().join("quarantine/1.ron")).unwrap();
+        assert_eq!(quarantined, "not a guild");
+        assert!(storage.list().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,308 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use log::{error, info};
+use poise::{serenity_prelude::GuildId, BoxFuture};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::Storage;
+use crate::{
+    data::{GuildMeta, Phrase},
+    matcher::join_keywords,
+    schema::{self, Stored},
+    Error,
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS guilds (
+    id INTEGER PRIMARY KEY,
+    meta TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS phrases (
+    guild_id INTEGER NOT NULL,
+    phrase TEXT NOT NULL,
+    keywords TEXT NOT NULL,
+    PRIMARY KEY (guild_id, phrase)
+);
+CREATE TABLE IF NOT EXISTS variants (
+    guild_id INTEGER NOT NULL,
+    phrase TEXT NOT NULL,
+    variant TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS backups (
+    guild_id INTEGER NOT NULL,
+    version INTEGER NOT NULL,
+    meta TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS quarantine (
+    guild_id INTEGER NOT NULL,
+    meta TEXT NOT NULL
+);
+";
+
+/// stores guilds in an sqlite database
+///
+/// each guild is kept as a ron row, phrases and their variants are mirrored into their own tables
+/// so they can be queried across guilds
+///
+/// rows of older schema versions are copied to `backups` before being rewritten,
+/// rows that fail to load are moved to `quarantine` instead of being overwritten
+pub struct Sqlite {
+    connection: Arc<Mutex<Connection>>,
+}
+impl Sqlite {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+    /// runs a blocking database operation off the async runtime
+    async fn with_connection<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> Result<T, rusqlite::Error> + Send + 'static,
+    ) -> Result<T, Error> {
+        let connection = self.connection.clone();
+        let result =
+            tokio::task::spawn_blocking(move || f(&mut connection.lock().unwrap())).await??;
+
+        Ok(result)
+    }
+}
+
+impl Storage for Sqlite {
+    fn load(&self, guild_id: GuildId) -> BoxFuture<'_, Result<Option<GuildMeta>, Error>> {
+        Box::pin(async move {
+            let meta = self
+                .with_connection(move |connection| {
+                    connection
+                        .query_row(
+                            "SELECT meta FROM guilds WHERE id = ?1",
+                            [guild_id.0 as i64],
+                            |row| row.get::<_, String>(0),
+                        )
+                        .optional()
+                })
+                .await?;
+
+            let Some(meta) = meta else {
+                return Ok(None)
+            };
+            let id = guild_id.0 as i64;
+
+            let decoded = match schema::decode(&meta) {
+                Ok(decoded) => decoded,
+                Err(err) => {
+                    error!("quarantining guild {}: {}", guild_id, err);
+                    self.with_connection(move |connection| {
+                        let transaction = connection.transaction()?;
+                        transaction.execute(
+                            "INSERT INTO quarantine (guild_id, meta) VALUES (?1, ?2)",
+                            params![id, meta],
+                        )?;
+                        transaction.execute("DELETE FROM guilds WHERE id = ?1", [id])?;
+                        transaction.commit()
+                    })
+                    .await?;
+                    return Err(err);
+                }
+            };
+
+            // keep the original and rewrite the row in the current format
+            if let Some(version) = decoded.migrated_from {
+                info!("migrating guild {} from version {}", guild_id, version);
+                self.with_connection(move |connection| {
+                    connection.execute(
+                        "INSERT INTO backups (guild_id, version, meta) VALUES (?1, ?2, ?3)",
+                        params![id, version, meta],
+                    )
+                })
+                .await?;
+                self.save(guild_id, &decoded.guild_meta).await?;
+            }
+
+            Ok(Some(decoded.guild_meta))
+        })
+    }
+    /// replaces the guild and its phrases in one transaction
+    fn save<'a>(
+        &'a self,
+        guild_id: GuildId,
+        guild_meta: &'a GuildMeta,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let meta = ron::to_string(&Stored::current(guild_meta))?;
+            let phrases = guild_meta
+                .phrases
+                .iter()
+                .map(
+                    |(
+                        phrase,
+                        Phrase {
+                            keywords, variants, ..
+                        },
+                    )| {
+                        // sorted, so unchanged phrases are written the same way every save
+                        let keywords = join_keywords(keywords);
+                        (phrase.clone(), keywords, variants.clone())
+                    },
+                )
+                .collect::<Vec<_>>();
+
+            self.with_connection(move |connection| {
+                let id = guild_id.0 as i64;
+                let transaction = connection.transaction()?;
+
+                transaction.execute(
+                    "INSERT INTO guilds (id, meta) VALUES (?1, ?2)
+                    ON CONFLICT (id) DO UPDATE SET meta = excluded.meta",
+                    params![id, meta],
+                )?;
+                transaction.execute("DELETE FROM phrases WHERE guild_id = ?1", [id])?;
+                transaction.execute("DELETE FROM variants WHERE guild_id = ?1", [id])?;
+                for (phrase, keywords, variants) in phrases {
+                    transaction.execute(
+                        "INSERT INTO phrases (guild_id, phrase, keywords) VALUES (?1, ?2, ?3)",
+                        params![id, phrase, keywords],
+                    )?;
+                    for variant in variants {
+                        transaction.execute(
+                            "INSERT INTO variants (guild_id, phrase, variant) VALUES (?1, ?2, ?3)",
+                            params![id, phrase, variant],
+                        )?;
+                    }
+                }
+
+                transaction.commit()
+            })
+            .await
+        })
+    }
+    fn delete(&self, guild_id: GuildId) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            self.with_connection(move |connection| {
+                let id = guild_id.0 as i64;
+                let transaction = connection.transaction()?;
+
+                transaction.execute("DELETE FROM phrases WHERE guild_id = ?1", [id])?;
+                transaction.execute("DELETE FROM variants WHERE guild_id = ?1", [id])?;
+                transaction.execute("DELETE FROM guilds WHERE id = ?1", [id])?;
+
+                transaction.commit()
+            })
+            .await
+        })
+    }
+    fn list(&self) -> BoxFuture<'_, Result<Vec<GuildId>, Error>> {
+        Box::pin(async move {
+            let ids = self
+                .with_connection(|connection| {
+                    let mut statement = connection.prepare("SELECT id FROM guilds")?;
+                    let ids = statement
+                        .query_map([], |row| row.get::<_, i64>(0))?
+                        .collect::<Result<Vec<_>, _>>();
+                    ids
+                })
+                .await?;
+
+            Ok(ids.into_iter().map(|id| GuildId(id as u64)).collect())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::testing;
+
+    const GUILD: GuildId = GuildId(1);
+
+    fn count(storage: &Sqlite, table: &str) -> i64 {
+        storage
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+    }
+    fn insert_row(storage: &Sqlite, meta: &str) {
+        storage
+            .connection
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO guilds (id, meta) VALUES (?1, ?2)",
+                params![GUILD.0 as i64, meta],
+            )
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn saves_loads_lists_and_deletes() {
+        let storage = Sqlite::open(":memory:").unwrap();
+        let guild_meta = testing::guild_meta();
+        storage.save(GUILD, &guild_meta).await.unwrap();
+        // saving again replaces the mirrored rows
+        storage.save(GUILD, &guild_meta).await.unwrap();
+
+        let loaded = storage.load(GUILD).await.unwrap().unwrap();
+        assert!(loaded.phrases == guild_meta.phrases);
+        assert_eq!(storage.list().await.unwrap(), [GUILD]);
+        assert_eq!(count(&storage, "phrases"), 1);
+        assert_eq!(count(&storage, "variants"), 2);
+        let keywords = storage
+            .connection
+            .lock()
+            .unwrap()
+            .query_row("SELECT keywords FROM phrases", [], |row| {
+                row.get::<_, String>(0)
+            })
+            .unwrap();
+        assert_eq!(keywords, "greetings, hello there");
+
+        storage.delete(GUILD).await.unwrap();
+        assert!(storage.load(GUILD).await.unwrap().is_none());
+        assert!(storage.list().await.unwrap().is_empty());
+        assert_eq!(count(&storage, "phrases"), 0);
+        assert_eq!(count(&storage, "variants"), 0);
+    }
+
+    #[tokio::test]
+    async fn backs_up_migrated_rows() {
+        let storage = Sqlite::open(":memory:").unwrap();
+        insert_row(&storage, &testing::unversioned_guild());
+
+        let loaded = storage.load(GUILD).await.unwrap().unwrap();
+        let keywords = HashSet::from(["hello there".to_owned()]);
+        assert_eq!(loaded.phrases["general kenobi"].keywords, keywords);
+        assert_eq!(count(&storage, "backups"), 1);
+
+        // the row was rewritten in the current format
+        let meta = storage
+            .connection
+            .lock()
+            .unwrap()
+            .query_row("SELECT meta FROM guilds", [], |row| row.get::<_, String>(0))
+            .unwrap();
+        assert_eq!(schema::decode(&meta).unwrap().migrated_from, None);
+        assert_eq!(count(&storage, "phrases"), 1);
+    }
+
+    #[tokio::test]
+    async fn quarantines_broken_rows() {
+        let storage = Sqlite::open(":memory:").unwrap();
+        insert_row(&storage, "not a guild");
+
+        assert!(storage.load(GUILD).await.is_err());
+        assert_eq!(count(&storage, "quarantine"), 1);
+        assert!(storage.list().await.unwrap().is_empty());
+    }
+}
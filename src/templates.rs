@@ -0,0 +1,330 @@
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use fastrand::Rng;
+use poise::serenity_prelude::{Cache, ChannelId, GuildId, Mentionable, User};
+
+/// a value a response can contain, written as `{name}`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Placeholder {
+    /// name of the author of the triggering message
+    Author,
+    /// mention of the author of the triggering message
+    AuthorMention,
+    /// mention of the channel
+    Channel,
+    /// name of the guild
+    Guild,
+    /// keyword of the phrase found in the conversation
+    Keyword,
+    /// name of a random cached member of the guild
+    RandomMember,
+    /// current date
+    Date,
+    /// number of times the phrase was used, including this time
+    Count,
+}
+impl Placeholder {
+    pub const ALL: &'static [Placeholder] = &[
+        Placeholder::Author,
+        Placeholder::AuthorMention,
+        Placeholder::Channel,
+        Placeholder::Guild,
+        Placeholder::Keyword,
+        Placeholder::RandomMember,
+        Placeholder::Date,
+        Placeholder::Count,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Placeholder::Author => "author",
+            Placeholder::AuthorMention => "author_mention",
+            Placeholder::Channel => "channel",
+            Placeholder::Guild => "guild",
+            Placeholder::Keyword => "keyword",
+            Placeholder::RandomMember => "random_member",
+            Placeholder::Date => "date",
+            Placeholder::Count => "count",
+        }
+    }
+}
+
+/// reason a template can't be parsed
+#[derive(Debug)]
+pub enum TemplateError {
+    /// `{name}` with an unknown name
+    Unknown(String),
+    /// `{` without a closing `}`
+    Unclosed,
+    /// `}` without an opening `{`
+    Unopened,
+}
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::Unknown(name) => {
+                let known = Placeholder::ALL
+                    .iter()
+                    .map(|placeholder| placeholder.name())
+                    .intersperse(", ")
+                    .collect::<String>();
+                write!(f, "unknown placeholder `{{{name}}}`, known are {known}")
+            }
+            TemplateError::Unclosed => write!(f, "`{{` is never closed, write `{{{{` for a brace"),
+            TemplateError::Unopened => write!(f, "`}}` is never opened, write `}}}}` for a brace"),
+        }
+    }
+}
+
+enum Part<'a> {
+    Text(&'a str),
+    Placeholder(Placeholder),
+}
+
+/// a parsed response
+///
+/// `{name}` is replaced by a placeholder, `{{` and `}}` are literal braces
+pub struct Template<'a> {
+    parts: Vec<Part<'a>>,
+}
+impl<'a> Template<'a> {
+    pub fn parse(text: &'a str) -> Result<Self, TemplateError> {
+        let mut parts = Vec::new();
+        let mut rest = text;
+
+        while let Some(index) = rest.find(['{', '}']) {
+            let (text, tail) = rest.split_at(index);
+            if !text.is_empty() {
+                parts.push(Part::Text(text));
+            }
+
+            if let Some(tail) = tail.strip_prefix("{{") {
+                parts.push(Part::Text("{"));
+                rest = tail;
+            } else if let Some(tail) = tail.strip_prefix("}}") {
+                parts.push(Part::Text("}"));
+                rest = tail;
+            } else if tail.starts_with('}') {
+                return Err(TemplateError::Unopened);
+            } else {
+                let end = tail.find('}').ok_or(TemplateError::Unclosed)?;
+                let name = &tail[1..end];
+                let placeholder = Placeholder::ALL
+                    .iter()
+                    .find(|placeholder| placeholder.name() == name)
+                    .ok_or_else(|| TemplateError::Unknown(name.to_owned()))?;
+                parts.push(Part::Placeholder(*placeholder));
+                rest = &tail[end + 1..];
+            }
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest));
+        }
+
+        Ok(Self { parts })
+    }
+    /// whether the template contains a placeholder, to skip gathering unused values
+    pub fn uses(&self, placeholder: Placeholder) -> bool {
+        self.parts
+            .iter()
+            .any(|part| matches!(part, Part::Placeholder(used) if *used == placeholder))
+    }
+    pub fn render(&self, values: &Values) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => *text,
+                Part::Placeholder(placeholder) => values.get(*placeholder),
+            })
+            .collect()
+    }
+}
+
+/// the values placeholders are replaced with
+#[derive(Default)]
+pub struct Values {
+    pub author: String,
+    pub author_mention: String,
+    pub channel: String,
+    pub guild: String,
+    pub keyword: String,
+    pub random_member: String,
+    pub date: String,
+    pub count: String,
+}
+impl Values {
+    fn get(&self, placeholder: Placeholder) -> &str {
+        match placeholder {
+            Placeholder::Author => &self.author,
+            Placeholder::AuthorMention => &self.author_mention,
+            Placeholder::Channel => &self.channel,
+            Placeholder::Guild => &self.guild,
+            Placeholder::Keyword => &self.keyword,
+            Placeholder::RandomMember => &self.random_member,
+            Placeholder::Date => &self.date,
+            Placeholder::Count => &self.count,
+        }
+    }
+}
+
+/// checks a response before it is stored
+pub fn validate(text: &str) -> Result<(), TemplateError> {
+    Template::parse(text).map(|_| ())
+}
+
+/// the message a response is sent for
+pub struct Trigger<'a> {
+    pub author: &'a User,
+    pub channel: ChannelId,
+    pub guild: GuildId,
+    pub keyword: Option<String>,
+    /// uses of the phrase, including this one
+    pub count: u64,
+}
+
+/// renders a response for a trigger, values the response doesn't use are not gathered
+pub fn render(
+    text: &str,
+    trigger: &Trigger,
+    cache: &Cache,
+    rng: &Rng,
+) -> Result<String, TemplateError> {
+    let template = Template::parse(text)?;
+
+    let mut values = Values {
+        author: trigger.author.name.clone(),
+        author_mention: trigger.author.mention().to_string(),
+        channel: trigger.channel.mention().to_string(),
+        keyword: trigger.keyword.clone().unwrap_or_default(),
+        count: trigger.count.to_string(),
+        ..Default::default()
+    };
+    if template.uses(Placeholder::Guild) {
+        values.guild = cache
+            .guild_field(trigger.guild, |guild| guild.name.clone())
+            .unwrap_or_default();
+    }
+    if template.uses(Placeholder::RandomMember) {
+        values.random_member =
+            random_member(cache, trigger.guild, rng).unwrap_or_else(|| trigger.author.name.clone());
+    }
+    if template.uses(Placeholder::Date) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        // discord shows timestamps in the timezone of the reader
+        values.date = format!("<t:{}:D>", now.as_secs());
+    }
+
+    Ok(template.render(&values))
+}
+
+/// name of a random member in the cache of a guild
+fn random_member(cache: &Cache, guild_id: GuildId, rng: &Rng) -> Option<String> {
+    let names = cache.guild_field(guild_id, |guild| {
+        guild
+            .members
+            .values()
+            .map(|member| member.user.name.clone())
+            .collect::<Vec<_>>()
+    })?;
+    if names.is_empty() {
+        return None;
+    }
+    Some(names[rng.usize(..names.len())].clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render_with(text: &str, values: &Values) -> String {
+        Template::parse(text).unwrap().render(values)
+    }
+
+    #[test]
+    fn plain_text_is_unchanged() {
+        assert_eq!(
+            render_with("hello there", &Values::default()),
+            "hello there"
+        );
+        assert_eq!(render_with("", &Values::default()), "");
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        let values = Values {
+            author: "kenobi".to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(render_with("{{author}}", &values), "{author}");
+        assert_eq!(render_with("{{{author}}}", &values), "{kenobi}");
+        assert_eq!(render_with("a }} b {{ c", &values), "a } b { c");
+    }
+
+    #[test]
+    fn rejects_unclosed_brace() {
+        assert!(matches!(
+            Template::parse("hello {author"),
+            Err(TemplateError::Unclosed)
+        ));
+        assert!(matches!(Template::parse("{"), Err(TemplateError::Unclosed)));
+    }
+
+    #[test]
+    fn rejects_unopened_brace() {
+        assert!(matches!(
+            Template::parse("hello author}"),
+            Err(TemplateError::Unopened)
+        ));
+        assert!(matches!(Template::parse("}"), Err(TemplateError::Unopened)));
+    }
+
+    #[test]
+    fn rejects_unknown_placeholder() {
+        let result = Template::parse("hello {grievous}");
+        assert!(matches!(result, Err(TemplateError::Unknown(name)) if name == "grievous"));
+        assert!(
+            matches!(Template::parse("{}"), Err(TemplateError::Unknown(name)) if name.is_empty())
+        );
+    }
+
+    #[test]
+    fn renders_every_placeholder() {
+        let values = Values {
+            author: "kenobi".to_owned(),
+            author_mention: "<@1>".to_owned(),
+            channel: "<#2>".to_owned(),
+            guild: "high ground".to_owned(),
+            keyword: "hello there".to_owned(),
+            random_member: "grievous".to_owned(),
+            date: "<t:0:D>".to_owned(),
+            count: "3".to_owned(),
+        };
+        let text = Placeholder::ALL
+            .iter()
+            .map(|placeholder| format!("{{{}}}", placeholder.name()))
+            .intersperse(" ".to_owned())
+            .collect::<String>();
+
+        let template = Template::parse(&text).unwrap();
+        assert!(Placeholder::ALL
+            .iter()
+            .all(|placeholder| template.uses(*placeholder)));
+        assert_eq!(
+            template.render(&values),
+            "kenobi <@1> <#2> high ground hello there grievous <t:0:D> 3"
+        );
+    }
+
+    #[test]
+    fn reports_unused_placeholders() {
+        let template = Template::parse("{author} said {keyword}").unwrap();
+        assert!(template.uses(Placeholder::Author));
+        assert!(template.uses(Placeholder::Keyword));
+        assert!(!template.uses(Placeholder::RandomMember));
+    }
+}
@@ -0,0 +1,268 @@
+//! in-process stand-ins for the remote api and the storage, used by tests
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use poise::{serenity_prelude::GuildId, BoxFuture};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+use crate::{
+    data::{Data, GuildMeta, Phrase},
+    settings::Settings,
+    storage::Storage,
+    Error,
+};
+
+/// a request the stub api received
+#[derive(Debug)]
+pub struct Request {
+    pub path: String,
+    pub body: Value,
+}
+
+/// an openai compatible api answering searches and embeddings on a local port
+///
+/// texts are embedded as which words of the vocabulary they contain,
+/// searches score documents by the cosine similarity of those embeddings scaled to `0..=100`
+pub struct StubApi {
+    pub url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+    server: JoinHandle<()>,
+}
+impl StubApi {
+    pub async fn start(vocabulary: &[&str]) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let vocabulary = vocabulary
+            .iter()
+            .map(|word| word.to_lowercase())
+            .collect::<Vec<_>>();
+
+        let server_requests = requests.clone();
+        let server = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                if let Err(err) = serve(stream, &vocabulary, &server_requests).await {
+                    panic!("stub api failed: {}", err);
+                }
+            }
+        });
+
+        Self {
+            url,
+            requests,
+            server,
+        }
+    }
+    /// a bot sending its api requests to the stub
+    pub fn data(&self) -> Data {
+        let settings = Settings {
+            api_token: Some("token".to_owned()),
+            api_url: self.url.clone(),
+            ..Default::default()
+        };
+        Data::new(settings, Box::new(Memory))
+    }
+    /// takes the requests received so far
+    pub fn requests(&self) -> Vec<Request> {
+        std::mem::take(&mut self.requests.lock().unwrap())
+    }
+}
+impl Drop for StubApi {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+/// answers a single request, every connection is closed after its response
+async fn serve(
+    mut stream: TcpStream,
+    vocabulary: &[String],
+    requests: &Mutex<Vec<Request>>,
+) -> Result<(), Error> {
+    let mut buffer = Vec::new();
+    let header_end = loop {
+        if let Some(end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+        let mut chunk = [0; 1024];
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Err("connection closed before the headers ended".into());
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+
+    let head = String::from_utf8(buffer[..header_end].to_vec())?;
+    let path = head
+        .split(' ')
+        .nth(1)
+        .ok_or("request without a path")?
+        .to_owned();
+    let content_length = head
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .map_or(Ok(0), |(_, value)| value.trim().parse::<usize>())?;
+
+    let mut body = buffer[header_end..].to_vec();
+    body.resize(content_length, 0);
+    let received = buffer.len() - header_end;
+    if received < content_length {
+        stream.read_exact(&mut body[received..]).await?;
+    }
+    let body = serde_json::from_slice::<Value>(&body)?;
+
+    let response = if path == "/embeddings" {
+        Some(embeddings(vocabulary, &body))
+    } else if path.starts_with("/engines/") && path.ends_with("/search") {
+        Some(search(vocabulary, &body))
+    } else {
+        None
+    };
+    requests.lock().unwrap().push(Request { path, body });
+
+    let (status, response) = match response {
+        Some(response) => ("200 OK", response.to_string()),
+        None => (
+            "404 Not Found",
+            json!({ "error": "unknown path" }).to_string(),
+        ),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{response}",
+        response.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
+
+/// strings of a json array field
+fn strings<'a>(body: &'a Value, field: &str) -> Vec<&'a str> {
+    body[field]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .collect()
+}
+
+fn embed(vocabulary: &[String], text: &str) -> Vec<f32> {
+    let text = text.to_lowercase();
+    vocabulary
+        .iter()
+        .map(|word| if text.contains(word) { 1.0 } else { 0.0 })
+        .collect()
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f64 {
+    let dot = a.iter().zip(b).map(|(a, b)| a * b).sum::<f32>();
+    let norm = |vector: &[f32]| vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if norm(a) == 0.0 || norm(b) == 0.0 {
+        0.0
+    } else {
+        (dot / (norm(a) * norm(b))) as f64
+    }
+}
+
+/// embeddings of every input, listed in reverse so clients have to order them by index
+fn embeddings(vocabulary: &[String], body: &Value) -> Value {
+    let data = strings(body, "input")
+        .into_iter()
+        .map(|input| embed(vocabulary, input))
+        .enumerate()
+        .rev()
+        .map(|(index, embedding)| json!({ "index": index, "embedding": embedding }))
+        .collect::<Vec<_>>();
+    json!({ "data": data })
+}
+
+fn search(vocabulary: &[String], body: &Value) -> Value {
+    let query = embed(vocabulary, body["query"].as_str().unwrap_or_default());
+    let data = strings(body, "documents")
+        .into_iter()
+        .enumerate()
+        .map(|(document, text)| {
+            let score = cosine(&embed(vocabulary, text), &query) * 100.0;
+            json!({ "document": document, "score": score })
+        })
+        .collect::<Vec<_>>();
+    json!({ "data": data })
+}
+
+/// a guild with one phrase that has keywords and variants
+pub fn guild_meta() -> GuildMeta {
+    let mut guild_meta = GuildMeta::default();
+    let entry = Phrase {
+        keywords: HashSet::from(["hello there".to_owned(), "greetings".to_owned()]),
+        variants: vec!["hello there".to_owned(), "you are a bold one".to_owned()],
+        ..Default::default()
+    };
+    guild_meta
+        .phrases
+        .insert("general kenobi".to_owned(), entry);
+    guild_meta
+}
+
+/// a guild as the baseline stored it, without a version
+pub fn unversioned_guild() -> String {
+    let model = ron::to_string(&gpt3_rs::Model::Babbage).unwrap();
+    format!(
+        r#"(phrases: {{"general kenobi": ["hello there"]}}, channels: [], config: (minimum_score: 20, max_context_len: 512, chance: 25, cooldown: 60, model: {model}))"#
+    )
+}
+
+/// a directory removed again when dropped
+pub struct TempDir(PathBuf);
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("catchphrase-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// a bot without an api, for the local backends
+pub fn data() -> Data {
+    Data::new(Settings::default(), Box::new(Memory))
+}
+
+/// storage that keeps nothing
+pub struct Memory;
+impl Storage for Memory {
+    fn load(&self, _guild_id: GuildId) -> BoxFuture<'_, Result<Option<GuildMeta>, Error>> {
+        Box::pin(async { Ok(None) })
+    }
+    fn save<'a>(
+        &'a self,
+        _guild_id: GuildId,
+        _guild_meta: &'a GuildMeta,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async { Ok(()) })
+    }
+    fn delete(&self, _guild_id: GuildId) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async { Ok(()) })
+    }
+    fn list(&self) -> BoxFuture<'_, Result<Vec<GuildId>, Error>> {
+        Box::pin(async { Ok(Vec::new()) })
+    }
+}